@@ -0,0 +1,67 @@
+//! With the `embed-assets` feature, writes a table of every file in `assets/` for
+//! `vfs.rs` to include, so the executable carries its own assets. Packs and compiled
+//! builds are left out, the game is built from the loose files.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+/// `settings::COMPILED_ASSETS_FOLDER`, the build script can't use the crate itself
+const COMPILED_ASSETS_FOLDER: &str = "build";
+
+/// Every file under `folder`, as `/` separated paths relative to `root`.
+fn collect_files(root: &Path, folder: &Path, files: &mut Vec<(String, PathBuf)>) {
+    let entries =
+        fs::read_dir(folder).unwrap_or_else(|e| panic!("Can't read {}: {}", folder.display(), e));
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_files(root, &path, files);
+            continue;
+        }
+        let relative = path
+            .strip_prefix(root)
+            .expect("file outside the asset folder");
+        let name: Vec<&str> = relative
+            .components()
+            .filter_map(|component| component.as_os_str().to_str())
+            .collect();
+        files.push((name.join("/"), path));
+    }
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    if env::var_os("CARGO_FEATURE_EMBED_ASSETS").is_none() {
+        return;
+    }
+
+    let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let assets_folder = manifest_dir.join("assets");
+    // cargo checks everything inside a directory for changes
+    println!("cargo:rerun-if-changed={}", assets_folder.display());
+
+    let mut files = vec![];
+    collect_files(&assets_folder, &assets_folder, &mut files);
+    // packs are already everything else in the folder, and a local `assets build` output
+    // can be older than the sprites while the game prefers it whenever it is there
+    let compiled_prefix = format!("{}/", COMPILED_ASSETS_FOLDER);
+    files.retain(|(name, _)| !name.ends_with(".pack") && !name.starts_with(&compiled_prefix));
+    // sorted so lookups can binary search
+    files.sort();
+
+    let mut table = String::from("pub static FILES: &[(&str, &[u8])] = &[\n");
+    for (name, path) in files {
+        table.push_str(&format!(
+            "    ({:?}, include_bytes!({:?})),\n",
+            name,
+            path.to_string_lossy()
+        ));
+    }
+    table.push_str("];\n");
+
+    let out_path = PathBuf::from(env::var("OUT_DIR").unwrap()).join("embedded_assets.rs");
+    fs::write(&out_path, table)
+        .unwrap_or_else(|e| panic!("Can't write {}: {}", out_path.display(), e));
+}
@@ -0,0 +1,496 @@
+//! Animation state machines, so which sprite an entity shows is picked by data instead
+//! of if/else chains in `step`. Each json file in the animations folder is a graph,
+//! named after the file:
+//!
+//! ```json
+//! {
+//!     "initial": "idle",
+//!     "states": {
+//!         "idle": { "sprite": "man_idle" },
+//!         "walk": { "sprite": "man_walk", "clip": "walk", "mode": "loop", "speed": 1.5 },
+//!         "wave": { "sprite": "man_wave", "mode": "once" }
+//!     },
+//!     "transitions": [
+//!         { "from": "idle", "to": "walk", "when": { "speed": { "above": 10 } } },
+//!         { "from": "*", "to": "idle", "when": { "speed": { "below": 10 } } },
+//!         { "from": "wave", "to": "walk", "when": { "moving": true }, "interrupt": true }
+//!     ]
+//! }
+//! ```
+//!
+//! Gameplay only sets params on an entity's `AnimationStateMachine`, numbers or flags.
+//! Every step the first transition out of the current state whose `when` all hold is
+//! taken, `"*"` is any state. A param that was never set is 0, or false.
+//!
+//! A transition with `"wait": true` is only taken once the clip has finished. Leaving a
+//! `once` state always waits for the clip, unless the transition says `"interrupt": true`.
+//! Going to the state already playing restarts it, so that waits too.
+
+use std::{collections::HashMap, path::Path};
+
+use serde_json::Value;
+
+use crate::{
+    error::AssetError,
+    sprite::{PlayMode, Sprite, SpriteAnimator, SpriteData, SpriteRegistry},
+    vfs::{join, Vfs},
+};
+
+/// What a transition needs a param to be.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Condition {
+    Above(f32),
+    Below(f32),
+    Is(bool),
+}
+
+impl Condition {
+    pub fn holds(self, value: f32) -> bool {
+        match self {
+            Condition::Above(limit) => value > limit,
+            Condition::Below(limit) => value < limit,
+            Condition::Is(flag) => (value != 0.0) == flag,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AnimationState {
+    pub name: String,
+    pub sprite: Sprite,
+    /// tag to play, `None` plays the whole sheet
+    pub clip: Option<String>,
+    pub mode: PlayMode,
+    pub speed: f32,
+}
+
+#[derive(Clone, Debug)]
+pub struct Transition {
+    /// `None` leaves from any state
+    pub from: Option<usize>,
+    pub to: usize,
+    pub when: Vec<(String, Condition)>,
+    pub wait: bool,
+    pub interrupt: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct AnimationGraph {
+    pub name: String,
+    pub states: Vec<AnimationState>,
+    pub initial: usize,
+    /// checked in order, the first that can be taken is
+    pub transitions: Vec<Transition>,
+}
+
+impl AnimationGraph {
+    pub fn get_state(&self, name: &str) -> Option<usize> {
+        self.states.iter().position(|state| state.name == name)
+    }
+}
+
+/// Where one entity is in a graph, and the params its transitions look at.
+#[derive(Clone, Debug)]
+pub struct AnimationStateMachine {
+    /// index into the loaded graphs
+    pub graph: usize,
+    pub state: usize,
+    params: HashMap<String, f32>,
+}
+
+impl AnimationStateMachine {
+    /// Starts the named graph in its initial state, switching the animator over to it.
+    /// `None` if there is no such graph.
+    pub fn start(
+        graphs: &[AnimationGraph],
+        name: &str,
+        animator: &mut SpriteAnimator,
+        sprites: &[SpriteData],
+    ) -> Option<Self> {
+        let graph = graphs.iter().position(|graph| graph.name == name)?;
+        let machine = Self {
+            graph,
+            state: graphs[graph].initial,
+            params: HashMap::new(),
+        };
+        machine.enter(graphs, animator, sprites);
+        Some(machine)
+    }
+
+    pub fn set_param(&mut self, name: &str, value: f32) {
+        match self.params.get_mut(name) {
+            Some(param) => *param = value,
+            None => {
+                self.params.insert(name.to_string(), value);
+            }
+        }
+    }
+
+    pub fn set_flag(&mut self, name: &str, flag: bool) {
+        self.set_param(name, if flag { 1.0 } else { 0.0 });
+    }
+
+    pub fn param(&self, name: &str) -> f32 {
+        self.params.get(name).copied().unwrap_or(0.0)
+    }
+
+    pub fn state_name<'a>(&self, graphs: &'a [AnimationGraph]) -> &'a str {
+        &graphs[self.graph].states[self.state].name
+    }
+
+    /// Takes the first transition that can be taken, if any. Returns whether the state changed.
+    pub fn step(
+        &mut self,
+        graphs: &[AnimationGraph],
+        animator: &mut SpriteAnimator,
+        sprites: &[SpriteData],
+    ) -> bool {
+        let graph = &graphs[self.graph];
+        let current = &graph.states[self.state];
+        let transition = graph.transitions.iter().find(|transition| {
+            if transition.from.is_some_and(|from| from != self.state) {
+                return false;
+            }
+            let waits = transition.wait
+                || transition.to == self.state
+                || (current.mode == PlayMode::Once && !transition.interrupt);
+            if waits && !animator.finished {
+                return false;
+            }
+            transition
+                .when
+                .iter()
+                .all(|(param, condition)| condition.holds(self.param(param)))
+        });
+        let Some(transition) = transition else {
+            return false;
+        };
+        self.state = transition.to;
+        self.enter(graphs, animator, sprites);
+        true
+    }
+
+    /// Plays the current state's clip from the start.
+    fn enter(
+        &self,
+        graphs: &[AnimationGraph],
+        animator: &mut SpriteAnimator,
+        sprites: &[SpriteData],
+    ) {
+        let state = &graphs[self.graph].states[self.state];
+        animator.set_sprite(state.sprite);
+        animator.requested_clip = state.clip.clone();
+        animator.mode = state.mode;
+        animator.speed = state.speed;
+        animator.next_clip = None;
+        animator.restart(sprites);
+    }
+
+    /// Moves over to graphs that were loaded again, by graph and state name. Falls back
+    /// to the initial state if the state is gone, and gives up if the graph is. A state
+    /// that now plays something else starts over with it.
+    pub fn refresh(
+        &mut self,
+        old_graphs: &[AnimationGraph],
+        graphs: &[AnimationGraph],
+        animator: &mut SpriteAnimator,
+        sprites: &[SpriteData],
+    ) -> bool {
+        let old_graph = &old_graphs[self.graph];
+        let Some(graph) = graphs.iter().position(|graph| graph.name == old_graph.name) else {
+            return false;
+        };
+        let old_state = &old_graph.states[self.state];
+        self.graph = graph;
+        match graphs[graph].get_state(&old_state.name) {
+            Some(state) => {
+                self.state = state;
+                if graphs[graph].states[state] != *old_state {
+                    self.enter(graphs, animator, sprites);
+                }
+            }
+            None => {
+                self.state = graphs[graph].initial;
+                self.enter(graphs, animator, sprites);
+            }
+        }
+        true
+    }
+}
+
+/// Fails naming every graph in `names` that isn't loaded, for checking the ones gameplay
+/// uses once at startup.
+pub fn require_graphs(graphs: &[AnimationGraph], names: &[&str]) -> Result<(), String> {
+    let missing: Vec<&str> = names
+        .iter()
+        .copied()
+        .filter(|name| !graphs.iter().any(|graph| graph.name == *name))
+        .collect();
+    if !missing.is_empty() {
+        return Err(format!("No animations named {}", missing.join(", ")));
+    }
+    Ok(())
+}
+
+/// Loads every graph in `folder`. States name sprites, which have to be in `registry`.
+pub fn load_animation_graphs(
+    vfs: &Vfs,
+    folder: &str,
+    registry: &SpriteRegistry,
+) -> Result<Vec<AnimationGraph>, AssetError> {
+    let mut graphs = vec![];
+    for file_name in vfs.list(folder)? {
+        let Some(name) = file_name.strip_suffix(".json") else {
+            continue;
+        };
+        let path = join(folder, &file_name);
+        let json = vfs.read_json(&path)?;
+        graphs.push(load_animation_graph(
+            &json,
+            name,
+            &vfs.full_path(&path),
+            registry,
+        )?);
+    }
+    Ok(graphs)
+}
+
+pub fn load_animation_graph(
+    json: &Value,
+    name: &str,
+    path: &Path,
+    registry: &SpriteRegistry,
+) -> Result<AnimationGraph, AssetError> {
+    let invalid = |field: String, cause: String| AssetError::InvalidField {
+        path: path.to_path_buf(),
+        frame: None,
+        field,
+        cause,
+    };
+
+    let states_data = json["states"]
+        .as_object()
+        .filter(|states| !states.is_empty())
+        .ok_or_else(|| {
+            invalid(
+                "states".to_string(),
+                "expected at least one state".to_string(),
+            )
+        })?;
+    let mut states = Vec::with_capacity(states_data.len());
+    for (state_name, state_data) in states_data {
+        let field = |key: &str| format!("states.{}.{}", state_name, key);
+        let sprite_name = state_data["sprite"]
+            .as_str()
+            .ok_or_else(|| invalid(field("sprite"), "expected a sprite name".to_string()))?;
+        let sprite = registry.lookup(sprite_name).ok_or_else(|| {
+            invalid(
+                field("sprite"),
+                format!("there is no sprite named {}", sprite_name),
+            )
+        })?;
+        let clip = match state_data.get("clip") {
+            Some(clip) => Some(
+                clip.as_str()
+                    .ok_or_else(|| invalid(field("clip"), "expected a tag name".to_string()))?
+                    .to_string(),
+            ),
+            None => None,
+        };
+        let mode = match state_data.get("mode") {
+            Some(mode) => mode.as_str().and_then(PlayMode::from_name).ok_or_else(|| {
+                invalid(
+                    field("mode"),
+                    "expected loop, once, pingpong or reverse".to_string(),
+                )
+            })?,
+            None => PlayMode::Loop,
+        };
+        let speed = match state_data.get("speed") {
+            Some(speed) => speed
+                .as_f64()
+                .filter(|speed| *speed >= 0.0)
+                .ok_or_else(|| invalid(field("speed"), "expected a number from 0".to_string()))?
+                as f32,
+            None => 1.0,
+        };
+        states.push(AnimationState {
+            name: state_name.clone(),
+            sprite,
+            clip,
+            mode,
+            speed,
+        });
+    }
+    let find_state = |state_name: &str, field: String| {
+        states
+            .iter()
+            .position(|state| state.name == state_name)
+            .ok_or_else(|| invalid(field, format!("there is no state named {}", state_name)))
+    };
+
+    let initial = match json.get("initial") {
+        Some(initial) => {
+            let initial = initial.as_str().ok_or_else(|| {
+                invalid("initial".to_string(), "expected a state name".to_string())
+            })?;
+            find_state(initial, "initial".to_string())?
+        }
+        None => 0,
+    };
+
+    let mut transitions = vec![];
+    if let Some(transitions_data) = json.get("transitions") {
+        let transitions_data = transitions_data
+            .as_array()
+            .ok_or_else(|| invalid("transitions".to_string(), "expected an array".to_string()))?;
+        for (i, transition_data) in transitions_data.iter().enumerate() {
+            let field = |key: &str| format!("transitions[{}].{}", i, key);
+            let from = match transition_data["from"].as_str() {
+                Some("*") => None,
+                Some(from) => Some(find_state(from, field("from"))?),
+                None => return Err(invalid(field("from"), "expected a state name".to_string())),
+            };
+            let to = transition_data["to"]
+                .as_str()
+                .ok_or_else(|| invalid(field("to"), "expected a state name".to_string()))?;
+            let to = find_state(to, field("to"))?;
+            let flag = |key: &str| match transition_data.get(key) {
+                Some(flag) => flag
+                    .as_bool()
+                    .ok_or_else(|| invalid(field(key), "expected true or false".to_string())),
+                None => Ok(false),
+            };
+            let wait = flag("wait")?;
+            let interrupt = flag("interrupt")?;
+
+            let mut when = vec![];
+            if let Some(when_data) = transition_data.get("when") {
+                let when_data = when_data
+                    .as_object()
+                    .ok_or_else(|| invalid(field("when"), "expected an object".to_string()))?;
+                for (param, condition_data) in when_data {
+                    let field = |key: &str| format!("transitions[{}].when.{}{}", i, param, key);
+                    if let Some(flag) = condition_data.as_bool() {
+                        when.push((param.clone(), Condition::Is(flag)));
+                        continue;
+                    }
+                    let limit =
+                        |key: &str| match condition_data.get(key) {
+                            Some(limit) => limit
+                                .as_f64()
+                                .map(|limit| Some(limit as f32))
+                                .ok_or_else(|| {
+                                    invalid(
+                                        field(&format!(".{}", key)),
+                                        "expected a number".to_string(),
+                                    )
+                                }),
+                            None => Ok(None),
+                        };
+                    let above = limit("above")?;
+                    let below = limit("below")?;
+                    if above.is_none() && below.is_none() {
+                        return Err(invalid(
+                            field(""),
+                            "expected true, false, above or below".to_string(),
+                        ));
+                    }
+                    when.extend(above.map(|limit| (param.clone(), Condition::Above(limit))));
+                    when.extend(below.map(|limit| (param.clone(), Condition::Below(limit))));
+                }
+            }
+
+            transitions.push(Transition {
+                from,
+                to,
+                when,
+                wait,
+                interrupt,
+            });
+        }
+    }
+
+    Ok(AnimationGraph {
+        name: name.to_string(),
+        states,
+        initial,
+        transitions,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{atlas::AtlasSettings, loader::load_sprites_cpu, loader::LoadProgress};
+
+    fn load_assets() -> (Vec<AnimationGraph>, Vec<SpriteData>, SpriteRegistry) {
+        let vfs = Vfs::Folder("assets".into());
+        let loaded = load_sprites_cpu(
+            &vfs,
+            "sprites",
+            SpriteRegistry::new(),
+            AtlasSettings::default(),
+            &LoadProgress::default(),
+        )
+        .unwrap();
+        let graphs = load_animation_graphs(&vfs, "animations", &loaded.registry).unwrap();
+        (graphs, loaded.sprites, loaded.registry)
+    }
+
+    #[test]
+    fn man_walks_when_moving() {
+        let (graphs, sprites, registry) = load_assets();
+        assert!(require_graphs(&graphs, &["man", "reticle"]).is_ok());
+        assert!(require_graphs(&graphs, &["man", "dog"]).is_err());
+        let man_idle = registry.lookup("man_idle").unwrap();
+        let man_walk = registry.lookup("man_walk").unwrap();
+        let mut animator = SpriteAnimator::new(man_idle, &sprites);
+        assert!(AnimationStateMachine::start(&graphs, "dog", &mut animator, &sprites).is_none());
+        let mut machine =
+            AnimationStateMachine::start(&graphs, "man", &mut animator, &sprites).unwrap();
+        assert_eq!(machine.state_name(&graphs), "idle");
+
+        // standing still stays put
+        assert!(!machine.step(&graphs, &mut animator, &sprites));
+        machine.set_param("speed", 100.0);
+        assert!(machine.step(&graphs, &mut animator, &sprites));
+        assert_eq!(machine.state_name(&graphs), "walk");
+        assert_eq!(animator.get_sprite(), man_walk);
+        assert!(!machine.step(&graphs, &mut animator, &sprites));
+
+        machine.set_param("speed", 0.0);
+        assert!(machine.step(&graphs, &mut animator, &sprites));
+        assert_eq!(machine.state_name(&graphs), "idle");
+        assert_eq!(animator.get_sprite(), man_idle);
+    }
+
+    #[test]
+    fn reloaded_states_play_their_new_setup() {
+        let (graphs, sprites, registry) = load_assets();
+        let man_idle = registry.lookup("man_idle").unwrap();
+        let man_walk = registry.lookup("man_walk").unwrap();
+        let mut animator = SpriteAnimator::new(man_idle, &sprites);
+        let mut machine =
+            AnimationStateMachine::start(&graphs, "man", &mut animator, &sprites).unwrap();
+
+        // nothing changed, nothing restarts
+        animator.current_frame = 1;
+        assert!(machine.refresh(&graphs, &graphs.clone(), &mut animator, &sprites));
+        assert_eq!(animator.current_frame, 1);
+
+        let mut new_graphs = graphs.clone();
+        let man = new_graphs
+            .iter_mut()
+            .find(|graph| graph.name == "man")
+            .unwrap();
+        let idle = man.get_state("idle").unwrap();
+        man.states[idle].sprite = man_walk;
+        assert!(machine.refresh(&graphs, &new_graphs, &mut animator, &sprites));
+        assert_eq!(machine.state_name(&new_graphs), "idle");
+        assert_eq!(animator.get_sprite(), man_walk);
+
+        // a graph that is gone can't be followed
+        assert!(!machine.refresh(&new_graphs, &[], &mut animator, &sprites));
+    }
+}
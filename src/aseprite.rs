@@ -0,0 +1,1011 @@
+//! Reads `.aseprite` / `.ase` files directly, so sprites can be used without
+//! exporting a png and json from Aseprite first.
+//!
+//! Follows the format described in
+//! https://github.com/aseprite/aseprite/blob/main/docs/ase-file-specs.md
+//! Only normal blending is supported when flattening layers, which is what
+//! our sprites use.
+
+use std::io::Read;
+
+use flate2::read::ZlibDecoder;
+use glam::{IVec2, UVec2};
+
+use crate::{
+    error::AssetError,
+    image::RgbaImage,
+    sidecar::SpriteDefaults,
+    sprite::{AnimationDirection, Frame, FrameEvent, PixelRect, Slice, SliceKey, SpriteData, Tag},
+    vfs::Vfs,
+};
+
+const FILE_MAGIC: u16 = 0xA5E0;
+const FRAME_MAGIC: u16 = 0xF1FA;
+const HEADER_SIZE: usize = 128;
+const FRAME_HEADER_SIZE: usize = 16;
+/// more colours than any palette has, keeps a broken chunk from asking for gigabytes
+const MAX_PALETTE_SIZE: usize = 1 << 16;
+/// bytes in a slice key without the 9-slice center or the pivot
+const SLICE_KEY_SIZE: usize = 20;
+
+const CHUNK_OLD_PALETTE: u16 = 0x0004;
+const CHUNK_LAYER: u16 = 0x2004;
+const CHUNK_CEL: u16 = 0x2005;
+const CHUNK_TAGS: u16 = 0x2018;
+const CHUNK_PALETTE: u16 = 0x2019;
+const CHUNK_USER_DATA: u16 = 0x2020;
+const CHUNK_SLICE: u16 = 0x2022;
+
+const HEADER_FLAG_LAYER_OPACITY_VALID: u32 = 1;
+const HEADER_FLAG_LAYERS_HAVE_UUID: u32 = 4;
+
+const LAYER_FLAG_VISIBLE: u16 = 1;
+const LAYER_FLAG_REFERENCE: u16 = 64;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ColorDepth {
+    Rgba,
+    Grayscale,
+    Indexed,
+}
+
+impl ColorDepth {
+    fn bytes_per_pixel(self) -> usize {
+        match self {
+            ColorDepth::Rgba => 4,
+            ColorDepth::Grayscale => 2,
+            ColorDepth::Indexed => 1,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct UserData {
+    pub text: Option<String>,
+    pub color: Option<[u8; 4]>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LayerKind {
+    Normal,
+    Group,
+    Tilemap,
+}
+
+#[derive(Debug)]
+pub struct Layer {
+    pub name: String,
+    pub flags: u16,
+    pub kind: LayerKind,
+    pub child_level: u16,
+    pub blend_mode: u16,
+    pub opacity: u8,
+    pub user_data: Option<UserData>,
+}
+
+impl Layer {
+    pub fn is_visible(&self) -> bool {
+        self.flags & LAYER_FLAG_VISIBLE != 0
+    }
+
+    pub fn is_reference(&self) -> bool {
+        self.flags & LAYER_FLAG_REFERENCE != 0
+    }
+}
+
+#[derive(Debug)]
+pub enum CelContent {
+    /// Pixels in the file's color depth, `width * height` of them.
+    Image {
+        width: u32,
+        height: u32,
+        pixels: Vec<u8>,
+    },
+    /// Same pixels as the cel on this layer in another frame.
+    Linked(usize),
+    /// Tilemap cels are read past but not rendered.
+    Tilemap,
+}
+
+#[derive(Debug)]
+pub struct Cel {
+    pub layer: usize,
+    pub x: i32,
+    pub y: i32,
+    pub opacity: u8,
+    pub z_index: i32,
+    pub content: CelContent,
+    pub user_data: Option<UserData>,
+}
+
+#[derive(Debug)]
+pub struct AsepriteFrame {
+    /// milliseconds
+    pub duration: u16,
+    pub cels: Vec<Cel>,
+}
+
+#[derive(Debug)]
+pub struct AsepriteTag {
+    pub name: String,
+    pub from: usize,
+    pub to: usize,
+    pub direction: AnimationDirection,
+    /// 0 means repeat forever
+    pub repeat: u16,
+    pub user_data: Option<UserData>,
+}
+
+#[derive(Debug)]
+pub struct AsepriteSliceKey {
+    pub frame: usize,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    /// nine-slice center as x, y, w, h relative to the slice bounds
+    pub center: Option<(i32, i32, u32, u32)>,
+    /// pivot relative to the slice bounds
+    pub pivot: Option<(i32, i32)>,
+}
+
+#[derive(Debug)]
+pub struct AsepriteSlice {
+    pub name: String,
+    pub keys: Vec<AsepriteSliceKey>,
+    pub user_data: Option<UserData>,
+}
+
+#[derive(Debug)]
+pub struct AsepriteFile {
+    pub width: u32,
+    pub height: u32,
+    pub color_depth: ColorDepth,
+    pub flags: u32,
+    pub transparent_index: u8,
+    pub palette: Vec<[u8; 4]>,
+    pub layers: Vec<Layer>,
+    pub frames: Vec<AsepriteFrame>,
+    pub tags: Vec<AsepriteTag>,
+    pub slices: Vec<AsepriteSlice>,
+    pub user_data: Option<UserData>,
+}
+
+/// Little endian cursor over the file bytes.
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len().saturating_sub(self.pos)
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.remaining() < len {
+            return Err(format!(
+                "Unexpected end of data at byte {}, wanted {} more",
+                self.pos, len
+            ));
+        }
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, len: usize) -> Result<(), String> {
+        self.bytes(len).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn i16(&mut self) -> Result<i16, String> {
+        Ok(self.u16()? as i16)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn i32(&mut self) -> Result<i32, String> {
+        Ok(self.u32()? as i32)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.u16()? as usize;
+        let bytes = self.bytes(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|e| format!("Invalid string: {}", e))
+    }
+}
+
+/// What the next user data chunk belongs to.
+enum UserDataTarget {
+    None,
+    Sprite,
+    Layer(usize),
+    Cel(usize, usize),
+    Tag(usize),
+    Slice(usize),
+}
+
+impl AsepriteFile {
+    pub fn load(vfs: &Vfs, path: &str) -> Result<Self, AssetError> {
+        let bytes = vfs.read(path)?;
+        Self::from_bytes(&bytes).map_err(|e| AssetError::decode(vfs.full_path(path), e))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let mut reader = Reader::new(bytes);
+
+        // header
+        if bytes.len() < HEADER_SIZE {
+            return Err(format!(
+                "Header is {} bytes, expected {}",
+                bytes.len(),
+                HEADER_SIZE
+            ));
+        }
+        let _file_size = reader.u32()?;
+        let magic = reader.u16()?;
+        if magic != FILE_MAGIC {
+            return Err(format!("Not an aseprite file, magic was {:#06x}", magic));
+        }
+        let num_frames = reader.u16()? as usize;
+        let width = reader.u16()? as u32;
+        let height = reader.u16()? as u32;
+        // an empty sheet has no frame to show, the json loader turns these away too
+        if num_frames == 0 {
+            return Err("Sprite has no frames".to_string());
+        }
+        if width == 0 || height == 0 {
+            return Err(format!("Sprite has an empty size {}x{}", width, height));
+        }
+        let color_depth = match reader.u16()? {
+            32 => ColorDepth::Rgba,
+            16 => ColorDepth::Grayscale,
+            8 => ColorDepth::Indexed,
+            depth => return Err(format!("Unsupported color depth: {}", depth)),
+        };
+        let flags = reader.u32()?;
+        let _speed = reader.u16()?;
+        reader.skip(8)?;
+        let transparent_index = reader.u8()?;
+        reader.pos = HEADER_SIZE;
+
+        let mut file = AsepriteFile {
+            width,
+            height,
+            color_depth,
+            flags,
+            transparent_index,
+            palette: vec![],
+            layers: vec![],
+            frames: Vec::with_capacity(num_frames),
+            tags: vec![],
+            slices: vec![],
+            user_data: None,
+        };
+
+        let mut has_new_palette = false;
+        for frame_index in 0..num_frames {
+            let frame_start = reader.pos;
+            let frame_size = reader.u32()? as usize;
+            let magic = reader.u16()?;
+            if magic != FRAME_MAGIC {
+                return Err(format!(
+                    "Frame {} has bad magic {:#06x}",
+                    frame_index, magic
+                ));
+            }
+            let _old_chunk_count = reader.u16()?;
+            let duration = reader.u16()?;
+            reader.pos = frame_start + FRAME_HEADER_SIZE;
+            let frame_end = frame_start + frame_size;
+            if frame_size < FRAME_HEADER_SIZE || frame_end > bytes.len() {
+                return Err(format!("Frame {} has bad size {}", frame_index, frame_size));
+            }
+
+            file.frames.push(AsepriteFrame {
+                duration,
+                cels: vec![],
+            });
+
+            let mut target = UserDataTarget::None;
+            while reader.pos < frame_end {
+                let chunk_start = reader.pos;
+                let chunk_size = reader.u32()? as usize;
+                let chunk_type = reader.u16()?;
+                let chunk_end = chunk_start + chunk_size;
+                if chunk_size < 6 || chunk_end > frame_end {
+                    return Err(format!(
+                        "Chunk {:#06x} in frame {} has bad size {}",
+                        chunk_type, frame_index, chunk_size
+                    ));
+                }
+                let mut chunk = Reader::new(&bytes[reader.pos..chunk_end]);
+
+                match chunk_type {
+                    CHUNK_LAYER => {
+                        file.layers.push(read_layer(&mut chunk, flags)?);
+                        target = UserDataTarget::Layer(file.layers.len() - 1);
+                    }
+                    CHUNK_CEL => {
+                        let cel = read_cel(&mut chunk, color_depth)?;
+                        let cels = &mut file.frames[frame_index].cels;
+                        cels.push(cel);
+                        target = UserDataTarget::Cel(frame_index, cels.len() - 1);
+                    }
+                    CHUNK_TAGS => {
+                        let first = file.tags.len();
+                        file.tags.extend(read_tags(&mut chunk)?);
+                        target = UserDataTarget::Tag(first);
+                    }
+                    CHUNK_PALETTE => {
+                        read_palette(&mut chunk, &mut file.palette)?;
+                        has_new_palette = true;
+                        // sprite user data sits right after the first palette
+                        target = if frame_index == 0 {
+                            UserDataTarget::Sprite
+                        } else {
+                            UserDataTarget::None
+                        };
+                    }
+                    CHUNK_OLD_PALETTE if !has_new_palette => {
+                        read_old_palette(&mut chunk, &mut file.palette)?;
+                    }
+                    CHUNK_SLICE => {
+                        file.slices.push(read_slice(&mut chunk)?);
+                        target = UserDataTarget::Slice(file.slices.len() - 1);
+                    }
+                    CHUNK_USER_DATA => {
+                        let user_data = read_user_data(&mut chunk)?;
+                        match target {
+                            UserDataTarget::None => {}
+                            UserDataTarget::Sprite => file.user_data = Some(user_data),
+                            UserDataTarget::Layer(i) => file.layers[i].user_data = Some(user_data),
+                            UserDataTarget::Cel(frame, i) => {
+                                file.frames[frame].cels[i].user_data = Some(user_data)
+                            }
+                            UserDataTarget::Tag(i) => {
+                                if let Some(tag) = file.tags.get_mut(i) {
+                                    tag.user_data = Some(user_data);
+                                }
+                                // each tag gets its own user data chunk, in order
+                                target = UserDataTarget::Tag(i + 1);
+                            }
+                            UserDataTarget::Slice(i) => file.slices[i].user_data = Some(user_data),
+                        }
+                    }
+                    // color profiles, tilesets, masks etc. are not needed
+                    _ => {}
+                }
+
+                reader.pos = chunk_end;
+            }
+            reader.pos = frame_end;
+        }
+
+        for tag in file.tags.iter() {
+            if tag.from > tag.to || tag.to >= file.frames.len() {
+                return Err(format!(
+                    "Tag {} has frame range {}..={} but there are {} frames",
+                    tag.name,
+                    tag.from,
+                    tag.to,
+                    file.frames.len()
+                ));
+            }
+        }
+
+        Ok(file)
+    }
+
+    pub fn size(&self) -> UVec2 {
+        UVec2::new(self.width, self.height)
+    }
+
+    /// Whether a layer and all the groups it sits in are visible.
+    fn layer_visibility(&self) -> Vec<bool> {
+        let mut visible = Vec::with_capacity(self.layers.len());
+        // visibility of the enclosing group at each child level
+        let mut parents: Vec<bool> = vec![];
+        for layer in self.layers.iter() {
+            let level = layer.child_level as usize;
+            parents.truncate(level);
+            let parent_visible = parents.last().copied().unwrap_or(true);
+            let is_visible = parent_visible && layer.is_visible() && !layer.is_reference();
+            visible.push(is_visible);
+            if layer.kind == LayerKind::Group {
+                parents.resize(level, true);
+                parents.push(is_visible);
+            }
+        }
+        visible
+    }
+
+    fn cel_image<'a>(&'a self, frame: usize, cel: &'a Cel) -> Option<(u32, u32, &'a [u8])> {
+        match &cel.content {
+            CelContent::Image {
+                width,
+                height,
+                pixels,
+            } => Some((*width, *height, pixels.as_slice())),
+            CelContent::Linked(linked_frame) => {
+                // a link to itself would recurse forever
+                if *linked_frame == frame {
+                    return None;
+                }
+                let linked = self
+                    .frames
+                    .get(*linked_frame)?
+                    .cels
+                    .iter()
+                    .find(|linked| linked.layer == cel.layer)?;
+                match &linked.content {
+                    CelContent::Image {
+                        width,
+                        height,
+                        pixels,
+                    } => Some((*width, *height, pixels.as_slice())),
+                    _ => None,
+                }
+            }
+            CelContent::Tilemap => None,
+        }
+    }
+
+    fn pixel_to_rgba(&self, pixel: &[u8]) -> [u8; 4] {
+        match self.color_depth {
+            ColorDepth::Rgba => [pixel[0], pixel[1], pixel[2], pixel[3]],
+            ColorDepth::Grayscale => [pixel[0], pixel[0], pixel[0], pixel[1]],
+            ColorDepth::Indexed => {
+                let index = pixel[0];
+                if index == self.transparent_index {
+                    [0, 0, 0, 0]
+                } else {
+                    self.palette
+                        .get(index as usize)
+                        .copied()
+                        .unwrap_or([0, 0, 0, 0])
+                }
+            }
+        }
+    }
+
+    /// Flattens all visible layers of a frame into one image the size of the canvas.
+    pub fn render_frame(&self, frame: usize) -> RgbaImage {
+        let mut image = RgbaImage::new(self.width, self.height);
+        self.render_frame_into(frame, &mut image, 0, 0);
+        image
+    }
+
+    fn render_frame_into(&self, frame: usize, image: &mut RgbaImage, offset_x: u32, offset_y: u32) {
+        let visible = self.layer_visibility();
+        let use_layer_opacity = self.flags & HEADER_FLAG_LAYER_OPACITY_VALID != 0;
+        let bytes_per_pixel = self.color_depth.bytes_per_pixel();
+
+        // cels draw in layer order, shifted by their z-index
+        let mut cels: Vec<&Cel> = self.frames[frame]
+            .cels
+            .iter()
+            .filter(|cel| visible.get(cel.layer).copied().unwrap_or(false))
+            .collect();
+        cels.sort_by_key(|cel| (cel.layer as i32 + cel.z_index, cel.z_index));
+
+        for cel in cels {
+            let Some((width, height, pixels)) = self.cel_image(frame, cel) else {
+                continue;
+            };
+            let layer_opacity = if use_layer_opacity {
+                self.layers[cel.layer].opacity
+            } else {
+                255
+            };
+            let opacity = (cel.opacity as u32 * layer_opacity as u32 / 255) as u8;
+
+            for y in 0..height {
+                let canvas_y = cel.y + y as i32;
+                if canvas_y < 0 || canvas_y >= self.height as i32 {
+                    continue;
+                }
+                for x in 0..width {
+                    let canvas_x = cel.x + x as i32;
+                    if canvas_x < 0 || canvas_x >= self.width as i32 {
+                        continue;
+                    }
+                    let i = (y * width + x) as usize * bytes_per_pixel;
+                    let color = self.pixel_to_rgba(&pixels[i..i + bytes_per_pixel]);
+                    image.blend_pixel(
+                        offset_x + canvas_x as u32,
+                        offset_y + canvas_y as u32,
+                        color,
+                        opacity,
+                    );
+                }
+            }
+        }
+    }
+
+    /// Renders every frame side by side, like a horizontal strip export.
+    pub fn render_sheet(&self) -> RgbaImage {
+        let mut sheet = RgbaImage::new(self.width * self.frames.len() as u32, self.height);
+        for frame in 0..self.frames.len() {
+            self.render_frame_into(frame, &mut sheet, frame as u32 * self.width, 0);
+        }
+        sheet
+    }
+
+    /// Frame layout matching `render_sheet`.
+    pub fn sprite_data(&self) -> SpriteData {
+        let frames = (0..self.frames.len())
+            .map(|i| Frame {
+                sample_position: UVec2::new(i as u32 * self.width, 0),
+                sample_size: self.size(),
+                trim_offset: UVec2::ZERO,
+                source_size: self.size(),
+                rotated: false,
+                page: 0,
+                duration: self.frames[i].duration as f32,
+            })
+            .collect();
+        let tags = self
+            .tags
+            .iter()
+            .map(|tag| Tag {
+                name: tag.name.clone(),
+                from: tag.from,
+                to: tag.to,
+                direction: tag.direction,
+            })
+            .collect();
+
+        let slices = self
+            .slices
+            .iter()
+            .map(|slice| {
+                let mut keys: Vec<SliceKey> = slice
+                    .keys
+                    .iter()
+                    .map(|key| SliceKey {
+                        frame: key.frame,
+                        bounds: PixelRect {
+                            position: IVec2::new(key.x, key.y),
+                            size: UVec2::new(key.width, key.height),
+                        },
+                        center: key.center.map(|(x, y, w, h)| PixelRect {
+                            position: IVec2::new(x, y),
+                            size: UVec2::new(w, h),
+                        }),
+                        pivot: key.pivot.map(|(x, y)| IVec2::new(x, y)),
+                    })
+                    .collect();
+                keys.sort_by_key(|key| key.frame);
+                Slice {
+                    name: slice.name.clone(),
+                    keys,
+                    data: slice.user_data.as_ref().and_then(|data| data.text.clone()),
+                }
+            })
+            .collect();
+
+        // a cel's user data text names the events on its frame, comma separated
+        let mut events = vec![];
+        for (i, frame) in self.frames.iter().enumerate() {
+            let texts = frame
+                .cels
+                .iter()
+                .filter_map(|cel| cel.user_data.as_ref()?.text.as_deref());
+            for name in texts.flat_map(|text| text.split(',')).map(str::trim) {
+                if !name.is_empty() {
+                    events.push(FrameEvent {
+                        frame: i,
+                        name: name.to_string(),
+                    });
+                }
+            }
+        }
+
+        SpriteData {
+            frames,
+            size: self.size(),
+            tags,
+            slices,
+            palette_frames: vec![],
+            defaults: SpriteDefaults::default(),
+            events,
+        }
+    }
+}
+
+fn read_layer(chunk: &mut Reader, header_flags: u32) -> Result<Layer, String> {
+    let flags = chunk.u16()?;
+    let kind = match chunk.u16()? {
+        0 => LayerKind::Normal,
+        1 => LayerKind::Group,
+        2 => LayerKind::Tilemap,
+        kind => return Err(format!("Unknown layer type {}", kind)),
+    };
+    let child_level = chunk.u16()?;
+    let _default_width = chunk.u16()?;
+    let _default_height = chunk.u16()?;
+    let blend_mode = chunk.u16()?;
+    let opacity = chunk.u8()?;
+    chunk.skip(3)?;
+    let name = chunk.string()?;
+    if kind == LayerKind::Tilemap {
+        let _tileset_index = chunk.u32()?;
+    }
+    if header_flags & HEADER_FLAG_LAYERS_HAVE_UUID != 0 {
+        chunk.skip(16)?;
+    }
+
+    Ok(Layer {
+        name,
+        flags,
+        kind,
+        child_level,
+        blend_mode,
+        opacity,
+        user_data: None,
+    })
+}
+
+fn read_cel(chunk: &mut Reader, color_depth: ColorDepth) -> Result<Cel, String> {
+    let layer = chunk.u16()? as usize;
+    let x = chunk.i16()? as i32;
+    let y = chunk.i16()? as i32;
+    let opacity = chunk.u8()?;
+    let cel_type = chunk.u16()?;
+    let z_index = chunk.i16()? as i32;
+    chunk.skip(5)?;
+
+    let content = match cel_type {
+        // raw
+        0 => {
+            let width = chunk.u16()? as u32;
+            let height = chunk.u16()? as u32;
+            let len = (width * height) as usize * color_depth.bytes_per_pixel();
+            let pixels = chunk.bytes(len)?.to_vec();
+            CelContent::Image {
+                width,
+                height,
+                pixels,
+            }
+        }
+        1 => CelContent::Linked(chunk.u16()? as usize),
+        // zlib compressed
+        2 => {
+            let width = chunk.u16()? as u32;
+            let height = chunk.u16()? as u32;
+            let len = (width * height) as usize * color_depth.bytes_per_pixel();
+            let compressed = chunk.bytes(chunk.remaining())?;
+            // the size comes from the file, only reserve what the data could plausibly hold
+            let mut pixels = Vec::with_capacity(len.min(compressed.len() * 4));
+            ZlibDecoder::new(compressed)
+                .take(len as u64)
+                .read_to_end(&mut pixels)
+                .map_err(|e| format!("Failed to decompress cel on layer {}: {}", layer, e))?;
+            if pixels.len() < len {
+                return Err(format!(
+                    "Cel on layer {} has {} bytes of pixels, expected {}",
+                    layer,
+                    pixels.len(),
+                    len
+                ));
+            }
+            pixels.truncate(len);
+            CelContent::Image {
+                width,
+                height,
+                pixels,
+            }
+        }
+        3 => CelContent::Tilemap,
+        cel_type => return Err(format!("Unknown cel type {}", cel_type)),
+    };
+
+    Ok(Cel {
+        layer,
+        x,
+        y,
+        opacity,
+        z_index,
+        content,
+        user_data: None,
+    })
+}
+
+fn read_tags(chunk: &mut Reader) -> Result<Vec<AsepriteTag>, String> {
+    let count = chunk.u16()?;
+    chunk.skip(8)?;
+    let mut tags = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let from = chunk.u16()? as usize;
+        let to = chunk.u16()? as usize;
+        let direction = match chunk.u8()? {
+            0 => AnimationDirection::Forward,
+            1 => AnimationDirection::Reverse,
+            2 => AnimationDirection::PingPong,
+            3 => AnimationDirection::PingPongReverse,
+            direction => return Err(format!("Unknown tag direction {}", direction)),
+        };
+        let repeat = chunk.u16()?;
+        // reserved bytes, then the deprecated tag color
+        chunk.skip(6 + 4)?;
+        let name = chunk.string()?;
+        tags.push(AsepriteTag {
+            name,
+            from,
+            to,
+            direction,
+            repeat,
+            user_data: None,
+        });
+    }
+    Ok(tags)
+}
+
+fn read_palette(chunk: &mut Reader, palette: &mut Vec<[u8; 4]>) -> Result<(), String> {
+    let size = chunk.u32()? as usize;
+    let first = chunk.u32()? as usize;
+    let last = chunk.u32()? as usize;
+    chunk.skip(8)?;
+    if last < first || last >= size.max(1) || size > MAX_PALETTE_SIZE {
+        return Err(format!(
+            "Bad palette range {}..={} of {}",
+            first, last, size
+        ));
+    }
+    palette.resize(size, [0, 0, 0, 255]);
+    for entry in palette.iter_mut().take(last + 1).skip(first) {
+        let flags = chunk.u16()?;
+        let rgba = chunk.bytes(4)?;
+        *entry = [rgba[0], rgba[1], rgba[2], rgba[3]];
+        if flags & 1 != 0 {
+            let _name = chunk.string()?;
+        }
+    }
+    Ok(())
+}
+
+fn read_old_palette(chunk: &mut Reader, palette: &mut Vec<[u8; 4]>) -> Result<(), String> {
+    let packets = chunk.u16()?;
+    let mut index = 0;
+    for _ in 0..packets {
+        index += chunk.u8()? as usize;
+        let count = match chunk.u8()? {
+            0 => 256,
+            count => count as usize,
+        };
+        if index + count > MAX_PALETTE_SIZE || count * 3 > chunk.remaining() {
+            return Err(format!(
+                "Bad old palette packet of {} colours at {}",
+                count, index
+            ));
+        }
+        if palette.len() < index + count {
+            palette.resize(index + count, [0, 0, 0, 255]);
+        }
+        for entry in palette.iter_mut().skip(index).take(count) {
+            let rgb = chunk.bytes(3)?;
+            *entry = [rgb[0], rgb[1], rgb[2], 255];
+        }
+        index += count;
+    }
+    Ok(())
+}
+
+fn read_slice(chunk: &mut Reader) -> Result<AsepriteSlice, String> {
+    let num_keys = chunk.u32()?;
+    let flags = chunk.u32()?;
+    let _reserved = chunk.u32()?;
+    let name = chunk.string()?;
+
+    let key_size =
+        SLICE_KEY_SIZE + if flags & 1 != 0 { 16 } else { 0 } + if flags & 2 != 0 { 8 } else { 0 };
+    if num_keys as usize > chunk.remaining() / key_size {
+        return Err(format!(
+            "Slice {} has {} keys, more than its chunk holds",
+            name, num_keys
+        ));
+    }
+    let mut keys = Vec::with_capacity(num_keys as usize);
+    for _ in 0..num_keys {
+        let frame = chunk.u32()? as usize;
+        let x = chunk.i32()?;
+        let y = chunk.i32()?;
+        let width = chunk.u32()?;
+        let height = chunk.u32()?;
+        let center = if flags & 1 != 0 {
+            Some((chunk.i32()?, chunk.i32()?, chunk.u32()?, chunk.u32()?))
+        } else {
+            None
+        };
+        let pivot = if flags & 2 != 0 {
+            Some((chunk.i32()?, chunk.i32()?))
+        } else {
+            None
+        };
+        keys.push(AsepriteSliceKey {
+            frame,
+            x,
+            y,
+            width,
+            height,
+            center,
+            pivot,
+        });
+    }
+
+    Ok(AsepriteSlice {
+        name,
+        keys,
+        user_data: None,
+    })
+}
+
+fn read_user_data(chunk: &mut Reader) -> Result<UserData, String> {
+    let flags = chunk.u32()?;
+    let text = if flags & 1 != 0 {
+        Some(chunk.string()?)
+    } else {
+        None
+    };
+    let color = if flags & 2 != 0 {
+        let rgba = chunk.bytes(4)?;
+        Some([rgba[0], rgba[1], rgba[2], rgba[3]])
+    } else {
+        None
+    };
+    // flag 4 adds typed properties maps, which we don't use
+    Ok(UserData { text, color })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::{image::decode_png, sprite::discover_sprites};
+
+    /// A header for a file with the given frame count and size, and nothing after it.
+    fn header(num_frames: u16, width: u16, height: u16) -> Vec<u8> {
+        let mut bytes = vec![0; HEADER_SIZE];
+        bytes[0..4].copy_from_slice(&(HEADER_SIZE as u32).to_le_bytes());
+        bytes[4..6].copy_from_slice(&FILE_MAGIC.to_le_bytes());
+        bytes[6..8].copy_from_slice(&num_frames.to_le_bytes());
+        bytes[8..10].copy_from_slice(&width.to_le_bytes());
+        bytes[10..12].copy_from_slice(&height.to_le_bytes());
+        bytes[12..14].copy_from_slice(&32u16.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn rejects_empty_sheets() {
+        assert!(AsepriteFile::from_bytes(&header(0, 8, 8)).is_err());
+        assert!(AsepriteFile::from_bytes(&header(1, 0, 8)).is_err());
+        assert!(AsepriteFile::from_bytes(&header(1, 8, 0)).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_headers() {
+        let bytes = header(1, 8, 8);
+        for len in [0, 4, 20, 40, HEADER_SIZE - 1] {
+            let error = AsepriteFile::from_bytes(&bytes[..len]).unwrap_err();
+            assert!(error.starts_with("Header is"), "{} bytes: {}", len, error);
+        }
+        // a whole header that promises a frame that isn't there
+        assert!(AsepriteFile::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let mut bytes = header(1, 8, 8);
+        bytes[4] = 0;
+        assert!(AsepriteFile::from_bytes(&bytes).is_err());
+        assert!(AsepriteFile::from_bytes(&bytes[..10]).is_err());
+    }
+
+    /// A slice chunk's contents, without the chunk header.
+    fn slice_chunk(num_keys: u32, flags: u32, keys: &[i32]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for value in [num_keys, flags, 0] {
+            bytes.extend(value.to_le_bytes());
+        }
+        bytes.extend(4u16.to_le_bytes());
+        bytes.extend(b"hand");
+        for value in keys {
+            bytes.extend(value.to_le_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn rejects_slices_with_more_keys_than_bytes() {
+        let key = [0, 1, 2, 3, 4];
+        let bytes = slice_chunk(1, 0, &key);
+        assert_eq!(read_slice(&mut Reader::new(&bytes)).unwrap().keys.len(), 1);
+
+        let error = read_slice(&mut Reader::new(&slice_chunk(u32::MAX, 0, &key))).unwrap_err();
+        assert!(error.contains("more than its chunk holds"), "{}", error);
+        // the key fits without the pivot the flags promise
+        assert!(read_slice(&mut Reader::new(&slice_chunk(1, 2, &key))).is_err());
+        assert!(read_slice(&mut Reader::new(&bytes[..bytes.len() - 1])).is_err());
+    }
+
+    #[test]
+    fn reads_slice_centers_and_pivots() {
+        let bounds = [3, -1, 2, 8, 16];
+        let center = [1, 2, 6, 12];
+        let pivot = [4, -8];
+        for flags in 0..4 {
+            let mut key = bounds.to_vec();
+            if flags & 1 != 0 {
+                key.extend(center);
+            }
+            if flags & 2 != 0 {
+                key.extend(pivot);
+            }
+            let slice = read_slice(&mut Reader::new(&slice_chunk(1, flags, &key))).unwrap();
+            assert_eq!(slice.name, "hand");
+            let key = &slice.keys[0];
+            assert_eq!(
+                (key.frame, key.x, key.y, key.width, key.height),
+                (3, -1, 2, 8, 16)
+            );
+            assert_eq!(key.center, (flags & 1 != 0).then_some((1, 2, 6, 12)));
+            assert_eq!(key.pivot, (flags & 2 != 0).then_some((4, -8)));
+        }
+    }
+
+    #[test]
+    fn renders_like_the_exported_sheets() {
+        let vfs = Vfs::Folder("assets".into());
+        let names = discover_sprites(&vfs, "sprites").unwrap();
+        assert!(!names.is_empty());
+        for name in names {
+            let ase_path = format!("sprites/{}.aseprite", name);
+            if !vfs.exists(&ase_path) {
+                continue;
+            }
+            let file = AsepriteFile::load(&vfs, &ase_path).unwrap();
+            let png_path = format!("sprites/{}.png", name);
+            let png = decode_png(&vfs.read(&png_path).unwrap(), Path::new(&png_path)).unwrap();
+
+            let sheet = file.render_sheet();
+            assert_eq!(sheet.size(), png.size(), "{} sheet size", name);
+            assert_eq!(
+                file.frames.len() as u32,
+                png.width / file.width,
+                "{} frame count",
+                name
+            );
+            assert_eq!(file.sprite_data().frames.len(), file.frames.len());
+            for y in 0..sheet.height {
+                for x in 0..sheet.width {
+                    assert_eq!(
+                        sheet.get_pixel(x, y),
+                        png.get_pixel(x, y),
+                        "{} pixel {},{}",
+                        name,
+                        x,
+                        y
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,391 @@
+//! Packs the frames of every sprite sheet into a few big atlas pages, so drawing
+//! a scene binds one texture instead of one per sprite.
+
+use glam::UVec2;
+
+use crate::{
+    error::AssetError,
+    image::RgbaImage,
+    settings::{ATLAS_EXTRUDE, ATLAS_PADDING, ATLAS_PAGE_SIZE},
+    sidecar::SpriteFilter,
+    sprite::SpriteSheet,
+};
+
+#[derive(Copy, Clone, Debug)]
+pub struct AtlasSettings {
+    /// pages are at most this wide and tall
+    pub page_size: u32,
+    /// empty pixels left between frames
+    pub padding: u32,
+    /// how far each frame's edge pixels are repeated outwards, stops neighbours
+    /// bleeding in when sampling with filtering or at fractional positions
+    pub extrude: u32,
+}
+
+impl Default for AtlasSettings {
+    fn default() -> Self {
+        Self {
+            page_size: ATLAS_PAGE_SIZE,
+            padding: ATLAS_PADDING,
+            extrude: ATLAS_EXTRUDE,
+        }
+    }
+}
+
+/// One frame waiting to be placed.
+struct PackItem {
+    sheet: usize,
+    /// 0 for the sheet's own image, otherwise the palette it was recoloured with
+    palette: usize,
+    frame: usize,
+    /// where the frame is in the sheet, frames get moved as the packing goes
+    sample_position: UVec2,
+    rotated: bool,
+    /// upright size of the frame's pixels
+    size: UVec2,
+    /// a page is sampled one way, so frames only share pages with the same filter
+    filter: SpriteFilter,
+}
+
+/// Fills pages left to right in rows, tallest frames first.
+struct ShelfPacker {
+    page_size: u32,
+    cursor: UVec2,
+    shelf_height: u32,
+    used: UVec2,
+}
+
+impl ShelfPacker {
+    fn new(page_size: u32) -> Self {
+        Self {
+            page_size,
+            cursor: UVec2::ZERO,
+            shelf_height: 0,
+            used: UVec2::ZERO,
+        }
+    }
+
+    /// Top left of the space for a `size` rect, or `None` if the page is full.
+    fn place(&mut self, size: UVec2) -> Option<UVec2> {
+        if self.cursor.x + size.x > self.page_size {
+            // start a new shelf under the current one
+            self.cursor = UVec2::new(0, self.cursor.y + self.shelf_height);
+            self.shelf_height = 0;
+        }
+        if self.cursor.x + size.x > self.page_size || self.cursor.y + size.y > self.page_size {
+            return None;
+        }
+        let position = self.cursor;
+        self.cursor.x += size.x;
+        self.shelf_height = self.shelf_height.max(size.y);
+        self.used = self.used.max(position + size);
+        Some(position)
+    }
+}
+
+/// Copies every frame of `sheets` into atlas pages and points the frames at their new home.
+/// Frames come out upright, so nothing is `rotated` afterwards.
+/// Recoloured copies of a sheet are packed too, into its `palette_frames`.
+pub fn pack_atlas(
+    sheets: &mut [SpriteSheet],
+    settings: &AtlasSettings,
+) -> Result<Vec<RgbaImage>, AssetError> {
+    let border = settings.extrude * 2 + settings.padding;
+
+    for sheet in sheets.iter_mut() {
+        // recolours share the layout of the sheet they were made from
+        sheet.data.palette_frames = vec![sheet.data.frames.clone(); sheet.recolors.len()];
+    }
+
+    let mut items = vec![];
+    for (sheet_index, sheet) in sheets.iter().enumerate() {
+        for (frame_index, frame) in sheet.data.frames.iter().enumerate() {
+            let size = frame.sample_size;
+            if size.x + border > settings.page_size || size.y + border > settings.page_size {
+                return Err(AssetError::Atlas {
+                    sprite: sheet.name.clone(),
+                    frame: frame_index,
+                    cause: format!(
+                        "{}x{} is too big for a {} atlas page",
+                        size.x, size.y, settings.page_size
+                    ),
+                });
+            }
+            let sheet_end = frame.sample_position + frame.sheet_size();
+            if sheet_end.x > sheet.image.width || sheet_end.y > sheet.image.height {
+                return Err(AssetError::Atlas {
+                    sprite: sheet.name.clone(),
+                    frame: frame_index,
+                    cause: format!(
+                        "reaches past the edge of its {}x{} image",
+                        sheet.image.width, sheet.image.height
+                    ),
+                });
+            }
+            for palette in 0..=sheet.recolors.len() {
+                items.push(PackItem {
+                    sheet: sheet_index,
+                    palette,
+                    frame: frame_index,
+                    sample_position: frame.sample_position,
+                    rotated: frame.rotated,
+                    size,
+                    filter: sheet.data.defaults.filter,
+                });
+            }
+        }
+    }
+    // tallest first keeps the shelves tight
+    items.sort_by(|a, b| {
+        a.filter
+            .cmp(&b.filter)
+            .then(b.size.y.cmp(&a.size.y))
+            .then(b.size.x.cmp(&a.size.x))
+    });
+
+    // place everything first so pages can be allocated at their final size
+    let mut placements = Vec::with_capacity(items.len());
+    let mut packers = vec![ShelfPacker::new(settings.page_size)];
+    for (i, item) in items.iter().enumerate() {
+        let padded = item.size + UVec2::splat(border);
+        let new_filter = i > 0 && items[i - 1].filter != item.filter;
+        let placed = match new_filter {
+            true => None,
+            false => packers.last_mut().unwrap().place(padded),
+        };
+        let position = match placed {
+            Some(position) => position,
+            None => {
+                let mut packer = ShelfPacker::new(settings.page_size);
+                let position = packer.place(padded).unwrap();
+                packers.push(packer);
+                position
+            }
+        };
+        placements.push((packers.len() - 1, position + UVec2::splat(settings.extrude)));
+    }
+
+    let mut pages: Vec<RgbaImage> = packers
+        .iter()
+        .map(|packer| RgbaImage::new(packer.used.x.max(1), packer.used.y.max(1)))
+        .collect();
+
+    for (item, (page, position)) in items.iter().zip(placements) {
+        let sheet = &mut sheets[item.sheet];
+        let image = match item.palette {
+            0 => &sheet.image,
+            palette => &sheet.recolors[palette - 1],
+        };
+        copy_frame(
+            image,
+            item.sample_position,
+            item.size,
+            item.rotated,
+            &mut pages[page],
+            position,
+            settings.extrude,
+        );
+
+        let frame = match item.palette {
+            0 => &mut sheet.data.frames[item.frame],
+            palette => &mut sheet.data.palette_frames[palette - 1][item.frame],
+        };
+        frame.page = page;
+        frame.sample_position = position;
+        frame.rotated = false;
+    }
+
+    Ok(pages)
+}
+
+/// Copies an upright `size` frame into the page at `position`, repeating the edge
+/// pixels `extrude` times around it.
+fn copy_frame(
+    sheet: &RgbaImage,
+    sample_position: UVec2,
+    size: UVec2,
+    rotated: bool,
+    page: &mut RgbaImage,
+    position: UVec2,
+    extrude: u32,
+) {
+    if size.x == 0 || size.y == 0 {
+        return;
+    }
+    let extrude = extrude as i64;
+    for y in -extrude..size.y as i64 + extrude {
+        for x in -extrude..size.x as i64 + extrude {
+            // pixels outside the frame copy the nearest edge pixel
+            let source_x = x.clamp(0, size.x as i64 - 1) as u32;
+            let source_y = y.clamp(0, size.y as i64 - 1) as u32;
+            // rotated frames are stored a quarter turn clockwise
+            let (sheet_x, sheet_y) = if rotated {
+                (size.y - 1 - source_y, source_x)
+            } else {
+                (source_x, source_y)
+            };
+            let color = sheet.get_pixel(sample_position.x + sheet_x, sample_position.y + sheet_y);
+            page.put_pixel(
+                (position.x as i64 + x) as u32,
+                (position.y as i64 + y) as u32,
+                color,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sidecar::SpriteDefaults, sprite::SpriteData};
+
+    /// A strip of `count` frames of `size`, every pixel a different colour.
+    fn strip(name: &str, count: u32, size: UVec2, filter: SpriteFilter) -> SpriteSheet {
+        let mut image = RgbaImage::new(size.x * count, size.y);
+        for y in 0..image.height {
+            for x in 0..image.width {
+                image.put_pixel(x, y, [name.len() as u8, x as u8, y as u8, 255]);
+            }
+        }
+        SpriteSheet {
+            name: name.to_string(),
+            data: SpriteData {
+                defaults: SpriteDefaults {
+                    filter,
+                    ..SpriteDefaults::default()
+                },
+                ..SpriteData::strip(count, size)
+            },
+            image,
+            recolors: vec![],
+        }
+    }
+
+    /// Packs the sheets and checks every frame landed inside its page, clear of the
+    /// others by the padding, with its pixels and extruded edges copied over.
+    fn pack_and_check(sheets: Vec<SpriteSheet>, settings: AtlasSettings) -> Vec<RgbaImage> {
+        let mut packed = sheets.clone();
+        let pages = pack_atlas(&mut packed, &settings).unwrap();
+        let extrude = settings.extrude;
+
+        let mut rects = vec![];
+        for (before, after) in sheets.iter().zip(&packed) {
+            for (source, frame) in before.data.frames.iter().zip(&after.data.frames) {
+                let page = &pages[frame.page];
+                let size = frame.sample_size;
+                assert!(frame.sample_position.x >= extrude && frame.sample_position.y >= extrude);
+                let end = frame.sample_position + size + UVec2::splat(extrude);
+                assert!(end.x <= page.width && end.y <= page.height);
+                assert!(page.width <= settings.page_size && page.height <= settings.page_size);
+
+                for y in 0..size.y {
+                    for x in 0..size.x {
+                        assert_eq!(
+                            page.get_pixel(
+                                frame.sample_position.x + x,
+                                frame.sample_position.y + y
+                            ),
+                            before.image.get_pixel(
+                                source.sample_position.x + x,
+                                source.sample_position.y + y
+                            ),
+                        );
+                    }
+                }
+                if extrude > 0 {
+                    let top_left = frame.sample_position;
+                    assert_eq!(
+                        page.get_pixel(top_left.x - extrude, top_left.y - extrude),
+                        page.get_pixel(top_left.x, top_left.y),
+                    );
+                }
+                rects.push((
+                    frame.page,
+                    frame.sample_position - UVec2::splat(extrude),
+                    end,
+                ));
+            }
+        }
+
+        for (i, (page, start, end)) in rects.iter().enumerate() {
+            for (other_page, other_start, other_end) in &rects[i + 1..] {
+                if page != other_page {
+                    continue;
+                }
+                let apart_x = end.x + settings.padding <= other_start.x
+                    || other_end.x + settings.padding <= start.x;
+                let apart_y = end.y + settings.padding <= other_start.y
+                    || other_end.y + settings.padding <= start.y;
+                assert!(apart_x || apart_y, "frames overlap on page {}", page);
+            }
+        }
+        pages
+    }
+
+    #[test]
+    fn frames_dont_overlap() {
+        let settings = AtlasSettings {
+            page_size: 128,
+            padding: 2,
+            extrude: 1,
+        };
+        let pages = pack_and_check(
+            vec![
+                strip("a", 4, UVec2::new(8, 8), SpriteFilter::Point),
+                strip("bb", 3, UVec2::new(16, 5), SpriteFilter::Point),
+                strip("ccc", 2, UVec2::new(3, 12), SpriteFilter::Point),
+            ],
+            settings,
+        );
+        assert_eq!(pages.len(), 1);
+    }
+
+    #[test]
+    fn full_pages_spill_onto_new_ones() {
+        let settings = AtlasSettings {
+            page_size: 24,
+            padding: 1,
+            extrude: 1,
+        };
+        let pages = pack_and_check(
+            vec![strip("a", 10, UVec2::new(8, 8), SpriteFilter::Point)],
+            settings,
+        );
+        assert!(pages.len() > 1);
+    }
+
+    #[test]
+    fn filters_get_their_own_pages() {
+        let settings = AtlasSettings {
+            page_size: 128,
+            padding: 0,
+            extrude: 0,
+        };
+        let sheets = vec![
+            strip("a", 2, UVec2::new(8, 8), SpriteFilter::Point),
+            strip("bb", 2, UVec2::new(8, 8), SpriteFilter::Bilinear),
+        ];
+        let mut packed = sheets.clone();
+        let pages = pack_atlas(&mut packed, &settings).unwrap();
+        assert_eq!(pages.len(), 2);
+        let page_of = |sheet: &SpriteSheet| sheet.data.frames[0].page;
+        assert_ne!(page_of(&packed[0]), page_of(&packed[1]));
+        assert!(packed[1]
+            .data
+            .frames
+            .iter()
+            .all(|frame| frame.page == page_of(&packed[1])));
+        pack_and_check(sheets, settings);
+    }
+
+    #[test]
+    fn frames_bigger_than_a_page_are_rejected() {
+        let settings = AtlasSettings {
+            page_size: 16,
+            padding: 2,
+            extrude: 1,
+        };
+        let mut sheets = vec![strip("a", 1, UVec2::new(14, 4), SpriteFilter::Point)];
+        assert!(pack_atlas(&mut sheets, &settings).is_err());
+    }
+}
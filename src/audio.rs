@@ -2,6 +2,12 @@ use raylib::audio::{Music, RaylibAudio, Sound};
 use strum::IntoEnumIterator;
 use strum_macros::EnumIter;
 
+use crate::{
+    error::AssetError,
+    settings::{MUSIC_FOLDER, SOUNDS_FOLDER},
+    vfs::{join, Vfs},
+};
+
 #[derive(Copy, Clone, Debug, EnumIter, PartialEq, Eq, Hash)]
 pub enum Song {
     Title,
@@ -34,49 +40,64 @@ pub fn get_sound_file_name(sound_effect: SoundEffect) -> &'static str {
     }
 }
 
+/// A song and the ogg file it streams from, raylib keeps reading the file while it
+/// plays. They only come as a pair so the file can't go before the song does.
+pub struct SongStream<'a> {
+    music: Music<'a>,
+    /// declared after `music` so it is dropped after it
+    _data: Vec<u8>,
+}
+
+impl<'a> SongStream<'a> {
+    pub fn music(&self) -> &Music<'a> {
+        &self.music
+    }
+}
+
 pub struct Audio<'a> {
     pub current_song: Option<Song>,
-    pub songs: Vec<Music<'a>>,
+    pub songs: Vec<SongStream<'a>>,
     pub sounds: Vec<Sound<'a>>,
     pub music_volume: f32,
     pub sound_effects_volume: f32,
 }
 
-pub fn load_songs(rl_audio: &RaylibAudio) -> Vec<Music> {
+pub fn load_songs<'a>(
+    rl_audio: &'a RaylibAudio,
+    vfs: &Vfs,
+) -> Result<Vec<SongStream<'a>>, AssetError> {
     let mut songs = Vec::new();
     for song in Song::iter() {
         let file_name_prefix = get_song_file_name(song);
-        let path = format!("assets/music/{}.ogg", file_name_prefix);
-        let music = match rl_audio.new_music(path.as_str()) {
-            Ok(music) => music,
-            Err(e) => {
-                panic!("Error loading music: {}", e);
-            }
-        };
-        songs.push(music);
+        let path = join(MUSIC_FOLDER, &format!("{}.ogg", file_name_prefix));
+        let data = vfs.read(&path)?;
+        let music = rl_audio
+            .new_music_from_memory(".ogg", &data)
+            .map_err(|e| AssetError::decode(vfs.full_path(&path), e))?;
+        songs.push(SongStream { music, _data: data });
     }
-    songs
+    Ok(songs)
 }
 
-pub fn load_sounds(rl_audio: &RaylibAudio) -> Vec<Sound> {
+pub fn load_sounds<'a>(rl_audio: &'a RaylibAudio, vfs: &Vfs) -> Result<Vec<Sound<'a>>, AssetError> {
     let mut sounds = Vec::new();
 
     for sound_effect in SoundEffect::iter() {
         let file_name_prefix = get_sound_file_name(sound_effect);
-        let path = format!("assets/sounds/{}.ogg", file_name_prefix);
-        let sound = match rl_audio.new_sound(path.as_str()) {
-            Ok(sound) => sound,
-            Err(e) => {
-                panic!("Error loading sound: {}", e);
-            }
-        };
+        let path = join(SOUNDS_FOLDER, &format!("{}.ogg", file_name_prefix));
+        let data = vfs.read(&path)?;
+        // sounds are decoded up front, so unlike music the data can go once they're made
+        let sound = rl_audio
+            .new_wave_from_memory(".ogg", &data)
+            .and_then(|wave| rl_audio.new_sound_from_wave(&wave))
+            .map_err(|e| AssetError::decode(vfs.full_path(&path), e))?;
         sounds.push(sound);
     }
-    sounds
+    Ok(sounds)
 }
 
 impl<'a> Audio<'a> {
-    pub fn new(songs: Vec<Music<'a>>, sounds: Vec<Sound<'a>>) -> Audio<'a> {
+    pub fn new(songs: Vec<SongStream<'a>>, sounds: Vec<Sound<'a>>) -> Audio<'a> {
         Self {
             current_song: None,
             songs,
@@ -96,7 +117,7 @@ impl<'a> Audio<'a> {
 
         self.current_song = Some(song);
 
-        let song = &mut self.songs[song as usize];
+        let song = self.songs[song as usize].music();
         song.set_volume(self.music_volume);
         song.play_stream();
     }
@@ -108,13 +129,13 @@ impl<'a> Audio<'a> {
     }
 
     fn stop_song(&mut self, song: Song) {
-        let song = &mut self.songs[song as usize];
+        let song = self.songs[song as usize].music();
         song.stop_stream();
     }
 
     pub fn update_current_song_stream_data(&mut self) {
         if let Some(song) = self.current_song {
-            let song = &mut self.songs[song as usize];
+            let song = self.songs[song as usize].music();
             song.update_stream();
         }
     }
@@ -127,7 +148,7 @@ impl<'a> Audio<'a> {
 
     pub fn set_current_song_volume(&mut self, volume: f32) {
         if let Some(song) = self.current_song {
-            let song = &mut self.songs[song as usize];
+            let song = self.songs[song as usize].music();
             song.set_volume(volume);
         }
     }
@@ -0,0 +1,98 @@
+//! Offline asset tool.
+//!
+//! cargo run --bin assets -- build [sprites folder] [output folder]
+//! cargo run --bin assets -- check [asset folder or pack]
+//! cargo run --bin assets -- pack [asset folder] [output file]
+
+use std::path::{Path, PathBuf};
+
+use spriteloading::{
+    atlas::AtlasSettings,
+    lint::check_assets,
+    manifest::{build_assets, MANIFEST_FILE},
+    pack::write_pack,
+    settings::{ASSETS_FOLDER, COMPILED_ASSETS_FOLDER, PACK_FILE, SPRITES_FOLDER},
+    vfs::Vfs,
+};
+
+const USAGE: &str = "usage: assets build [sprites folder] [output folder]
+       assets check [asset folder or pack]
+       assets pack [asset folder] [output file]";
+
+/// The path given on the command line, or `default` inside the asset folder.
+fn path_arg(args: &[String], i: usize, default: &str) -> PathBuf {
+    args.get(i)
+        .map_or_else(|| Path::new(ASSETS_FOLDER).join(default), PathBuf::from)
+}
+
+fn build(args: &[String]) -> Result<(), String> {
+    let sprites_folder = path_arg(args, 0, SPRITES_FOLDER);
+    let out_folder = path_arg(args, 1, COMPILED_ASSETS_FOLDER);
+
+    let vfs = Vfs::Folder(sprites_folder);
+    let compiled = build_assets(&vfs, "", &out_folder, &AtlasSettings::default())
+        .map_err(|e| e.to_string())?;
+    let num_frames: usize = compiled.sprites.iter().map(|data| data.frames.len()).sum();
+    println!(
+        "Packed {} sprites, {} frames into {} atlas pages",
+        compiled.sprites.len(),
+        num_frames,
+        compiled.pages.len()
+    );
+    for (file_name, size) in compiled.pages.iter().zip(compiled.page_sizes.iter()) {
+        println!(
+            "\t{} {}x{}",
+            out_folder.join(file_name).display(),
+            size.x,
+            size.y
+        );
+    }
+    println!("Wrote {}", out_folder.join(MANIFEST_FILE).display());
+    Ok(())
+}
+
+/// Prints every problem, failing if there are any.
+fn check(args: &[String]) -> Result<(), String> {
+    let root = args.first().map_or(ASSETS_FOLDER, |arg| arg.as_str());
+    let vfs = Vfs::open(Path::new(root)).map_err(|e| e.to_string())?;
+    let problems = check_assets(&vfs);
+    for problem in problems.iter() {
+        println!("{}", problem);
+    }
+    if problems.is_empty() {
+        println!("No problems found");
+        Ok(())
+    } else {
+        Err(format!("{} problems found", problems.len()))
+    }
+}
+
+fn pack(args: &[String]) -> Result<(), String> {
+    let asset_folder = args.first().map_or(ASSETS_FOLDER, |arg| arg.as_str());
+    let out_path = args.get(1).map_or(PACK_FILE, |arg| arg.as_str());
+
+    let names =
+        write_pack(Path::new(asset_folder), Path::new(out_path)).map_err(|e| e.to_string())?;
+    let size = std::fs::metadata(out_path).map_or(0, |meta| meta.len());
+    println!(
+        "Packed {} files into {}, {} bytes",
+        names.len(),
+        out_path,
+        size
+    );
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.first().map(|arg| arg.as_str()) {
+        Some("build") => build(&args[1..]),
+        Some("check") => check(&args[1..]),
+        Some("pack") => pack(&args[1..]),
+        _ => Err(USAGE.to_string()),
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
@@ -5,11 +5,73 @@ use raylib::{
     math::{Rectangle, Vector2},
 };
 
-use crate::{entity::Entity, graphics::Graphics, settings::SCREEN_DIMS, state::State};
+use crate::{
+    entity::Entity, graphics::Graphics, settings::SCREEN_DIMS, sprite::Frame, state::State,
+};
+
+/// What to pass to `draw_texture_pro` to draw a frame with a chosen point on it
+/// at the destination position.
+struct FrameQuad {
+    source: Rectangle,
+    size: Vec2,
+    origin: Vec2,
+    rotation: f32,
+}
+
+/// `anchor` is in the frame's untrimmed pixel space. Trimmed frames get shifted back
+/// to where they were cut from and rotated frames are turned upright again.
+/// `flip_x` and `flip_y` mirror the upright frame, anchor included.
+fn frame_quad(frame: &Frame, anchor: Vec2, scale: Vec2, flip_x: bool, flip_y: bool) -> FrameQuad {
+    let sheet_size = frame.sheet_size().as_vec2();
+    let mut source = Rectangle::new(
+        frame.sample_position.x as f32,
+        frame.sample_position.y as f32,
+        sheet_size.x,
+        sheet_size.y,
+    );
+    let mut anchor = anchor - frame.trim_offset.as_vec2();
+    if flip_x {
+        // raylib samples a negative source size backwards
+        anchor.x = frame.sample_size.x as f32 - anchor.x;
+        if frame.rotated {
+            source.height = -source.height;
+        } else {
+            source.width = -source.width;
+        }
+    }
+    if flip_y {
+        anchor.y = frame.sample_size.y as f32 - anchor.y;
+        // rotated frames have their upright y axis along the sheet's x axis
+        if frame.rotated {
+            source.width = -source.width;
+        } else {
+            source.height = -source.height;
+        }
+    }
+
+    if frame.rotated {
+        // stored a quarter turn clockwise, so draw it a quarter turn back.
+        // that points the quad's x axis up the screen and its y axis to the right
+        let height = frame.sample_size.y as f32;
+        FrameQuad {
+            source,
+            size: Vec2::new(sheet_size.x * scale.y, sheet_size.y * scale.x),
+            origin: Vec2::new((height - anchor.y) * scale.y, anchor.x * scale.x),
+            rotation: -90.0,
+        }
+    } else {
+        FrameQuad {
+            source,
+            size: sheet_size * scale,
+            origin: anchor * scale,
+            rotation: 0.0,
+        }
+    }
+}
 
 pub fn draw_entities(d: &mut RaylibDrawHandle, graphics: &Graphics, state: &State) {
     // Create a vector of mutable references to entities
-    let mut sorted_entities: Vec<&Entity> = state.entities.iter().collect();
+    let mut sorted_entities: Vec<&Entity> = state.iter().map(|(_, entity)| entity).collect();
 
     // // Sort the entities based on their y-foot-position
     // sorted_entities.sort_by(|a, b| {
@@ -47,81 +109,79 @@ pub fn draw_entities(d: &mut RaylibDrawHandle, graphics: &Graphics, state: &Stat
         shadow_min_scale_y + (shadow_max_scale_y - shadow_min_scale_y) * (time * 5.0).sin() as f32;
     for entity in sorted_entities.iter() {
         let sprite_data = graphics.get_sprite_data(entity.sprite_animator.sprite);
-        let frame = &sprite_data.frames[entity.sprite_animator.current_frame];
+        if !sprite_data.defaults.shadow {
+            continue;
+        }
+        let current_frame = entity.sprite_animator.current_frame;
+        let frame = &sprite_data.frames_for_palette(entity.palette)[current_frame];
 
         let scale = entity.sprite_animator.scale;
-        let sprite_scaled_size = sprite_data.size.as_vec2() * scale;
 
-        // Calculate shadow dimensions
-        let shadow_width = sprite_scaled_size.x;
-        let shadow_height = sprite_scaled_size.y * shadow_scale_y;
+        // Squash the sprite vertically into a shadow, pinned at the entity's feet
+        let anchor = sprite_data.anchor(current_frame);
+        let render = &entity.render;
+        let quad = frame_quad(
+            frame,
+            anchor,
+            Vec2::new(scale, scale * shadow_scale_y),
+            entity.sprite_animator.flip_x != render.flip_x,
+            render.flip_y,
+        );
 
         // Calculate shadow position (at the entity's feet)
         let shadow_position = entity.position;
 
-        // Origin is at the bottom center of the shadow
-        let origin = Vector2::new(shadow_width / 2.0, shadow_height);
-
         d.draw_texture_pro(
-            graphics.get_sprite_texture(entity.sprite_animator.sprite),
-            Rectangle::new(
-                frame.sample_position.x as f32,
-                frame.sample_position.y as f32,
-                sprite_data.size.x as f32,
-                sprite_data.size.y as f32,
-            ),
+            graphics.get_frame_texture(frame),
+            quad.source,
             Rectangle::new(
                 shadow_position.x,
                 shadow_position.y,
-                shadow_width,
-                shadow_height,
+                quad.size.x,
+                quad.size.y,
             ),
-            origin,
-            shadow_angle,
-            Color::new(0, 0, 0, 100),
+            Vector2::new(quad.origin.x, quad.origin.y),
+            quad.rotation + render.rotation + shadow_angle,
+            Color::new(0, 0, 0, (100.0 * render.alpha.clamp(0.0, 1.0)) as u8),
         );
     }
 
     // Draw the sorted entities
     for entity in sorted_entities.iter() {
         let sprite_data = graphics.get_sprite_data(entity.sprite_animator.sprite);
-        let frame = &sprite_data.frames[entity.sprite_animator.current_frame];
-        let position = entity.position;
+        let current_frame = entity.sprite_animator.current_frame;
+        let frame = &sprite_data.frames_for_palette(entity.palette)[current_frame];
+        let render = &entity.render;
+        let position = entity.position + render.offset;
         let scale = entity.sprite_animator.scale;
-        let sprite_scaled_size = sprite_data.size.as_vec2() * scale;
         // we use feet style origin, so the origin is at the bottom center of the sprite
-        let origin = Vec2::new(sprite_scaled_size.x / 2.0, sprite_scaled_size.y);
+        // unless the art has an anchor slice
+        let quad = frame_quad(
+            frame,
+            sprite_data.anchor(current_frame),
+            Vec2::splat(scale),
+            entity.sprite_animator.flip_x != render.flip_x,
+            render.flip_y,
+        );
         // draw a debug blue rect at the origin of the sprite
         // d.draw_rectangle_lines_ex(
-        //     Rectangle::new(position.x - origin.x, position.y - origin.y, 2.0, 2.0),
+        //     Rectangle::new(position.x, position.y, 2.0, 2.0),
         //     2.0,
         //     Color::BLUE,
         // );
 
-        let draw_position = position - origin;
-
         d.draw_texture_pro(
-            graphics.get_sprite_texture(entity.sprite_animator.sprite),
-            Rectangle::new(
-                frame.sample_position.x as f32,
-                frame.sample_position.y as f32,
-                sprite_data.size.x as f32,
-                sprite_data.size.y as f32,
-            ),
-            Rectangle::new(
-                draw_position.x,
-                draw_position.y,
-                sprite_data.size.x as f32 * scale,
-                sprite_data.size.y as f32 * scale,
-            ),
-            Vector2::zero(),
-            0.0,
-            Color::WHITE,
+            graphics.get_frame_texture(frame),
+            quad.source,
+            Rectangle::new(position.x, position.y, quad.size.x, quad.size.y),
+            Vector2::new(quad.origin.x, quad.origin.y),
+            quad.rotation + render.rotation,
+            render.color(),
         );
     }
 
     // draw boxes around entities
-    // for entity in state.entities.iter() {
+    // for (_, entity) in state.iter() {
     //     // dont forget the position is actually the center bottom, the foot origin, so we have to shif the rect.
     //     let bounds = entity.get_bounds();
     //     let tl = bounds.top_left;
@@ -133,3 +193,15 @@ pub fn draw_entities(d: &mut RaylibDrawHandle, graphics: &Graphics, state: &Stat
     //     );
     // }
 }
+
+/// A progress bar for while the sprites load, `progress` is from 0 to 1.
+pub fn draw_loading_screen(d: &mut RaylibDrawHandle, progress: f32) {
+    d.clear_background(Color::BLACK);
+    let width = SCREEN_DIMS.x as i32 / 2;
+    let height = 20;
+    let x = (SCREEN_DIMS.x as i32 - width) / 2;
+    let y = (SCREEN_DIMS.y as i32 - height) / 2;
+    d.draw_text("Loading", x, y - 30, 20, Color::WHITE);
+    d.draw_rectangle(x, y, (width as f32 * progress) as i32, height, Color::WHITE);
+    d.draw_rectangle_lines(x, y, width, height, Color::WHITE);
+}
@@ -1,6 +1,18 @@
 use glam::Vec2;
+use raylib::color::Color;
 
-use crate::sprite::{Sprite, SpriteAnimator};
+use crate::{
+    animation::{AnimationGraph, AnimationStateMachine},
+    sprite::{Sprite, SpriteAnimator, SpriteData},
+};
+
+/// A handle to an entity in `State`. It stops finding anything once the entity is
+/// removed, even after its slot is reused for another one.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct EntityId {
+    pub(crate) index: u32,
+    pub(crate) generation: u32,
+}
 
 #[derive(Debug, PartialEq)]
 pub enum EntityType {
@@ -11,14 +23,74 @@ pub enum EntityType {
     Log,
 }
 
+/// What gameplay uses an entity for, looked up with `State::with_role` so nothing
+/// depends on the order entities were spawned in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Role {
+    /// follows the mouse and arrow keys, and chops
+    Cursor,
+    /// men touching it get a new follower and it moves somewhere else
+    Pickup,
+}
+
+/// How an entity is drawn on top of what its sprite says. Only the picture changes,
+/// the hitbox and shadow stay where the entity is.
+#[derive(Copy, Clone, Debug)]
+pub struct RenderSettings {
+    /// multiplied with the sprite's colours
+    pub tint: Color,
+    /// from 0, invisible, to 1, multiplied with the tint's alpha
+    pub alpha: f32,
+    /// degrees clockwise around the sprite's anchor
+    pub rotation: f32,
+    /// mirrors around the anchor, on top of any flip the animator does for facing
+    pub flip_x: bool,
+    pub flip_y: bool,
+    /// moves the picture away from the entity position
+    pub offset: Vec2,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            tint: Color::WHITE,
+            alpha: 1.0,
+            rotation: 0.0,
+            flip_x: false,
+            flip_y: false,
+            offset: Vec2::ZERO,
+        }
+    }
+}
+
+impl RenderSettings {
+    /// The tint with `alpha` applied.
+    pub fn color(&self) -> Color {
+        Color {
+            a: (self.tint.a as f32 * self.alpha.clamp(0.0, 1.0)) as u8,
+            ..self.tint
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Entity {
     pub entity_type: EntityType,
+    pub roles: Vec<Role>,
     pub position: Vec2,
     pub size: Vec2,
+    /// moves the collision bounds away from the feet, see `fit_hitbox_to_sprite`
+    pub hitbox_offset: Vec2,
+    /// which of the sprite's palettes to draw with, 0 is the art as drawn
+    pub palette: usize,
     pub velocity: Vec2,
     pub sprite_animator: SpriteAnimator,
-    pub follows: Option<usize>,
+    /// picks the animator's sprite and clip, `None` leaves that to gameplay code
+    pub animation: Option<AnimationStateMachine>,
+    pub render: RenderSettings,
+    /// seconds left of the flash after being hit
+    pub hit_flash: f32,
+    pub follows: Option<EntityId>,
     pub hp: f32,
     pub friction: Option<f32>,
     pub expire_in: Option<f32>,
@@ -40,28 +112,87 @@ impl Bounds {
 }
 
 impl Entity {
-    pub fn new(entity_type: EntityType, position: Vec2, size: Vec2, sprite: Sprite) -> Self {
-        Self {
+    /// Scaled and sized from the sprite's defaults. Sprites without a hitbox give the
+    /// entity the size of their frames.
+    pub fn new(
+        entity_type: EntityType,
+        position: Vec2,
+        sprite: Sprite,
+        sprites: &[SpriteData],
+    ) -> Self {
+        let sprite_animator = SpriteAnimator::new(sprite, sprites);
+        let size = sprites[sprite.index()].size.as_vec2() * sprite_animator.scale;
+        let mut entity = Self {
             entity_type,
+            roles: vec![],
             position,
             size,
+            hitbox_offset: Vec2::ZERO,
+            palette: 0,
             velocity: Vec2::ZERO,
-            sprite_animator: SpriteAnimator::new(sprite),
+            sprite_animator,
+            animation: None,
+            render: RenderSettings::default(),
+            hit_flash: 0.0,
             follows: None,
             hp: 100.0,
             friction: None,
             expire_in: None,
             active: true,
-        }
+        };
+        entity.fit_hitbox_to_sprite(sprites);
+        entity
+    }
+
+    pub fn has_role(&self, role: Role) -> bool {
+        self.roles.contains(&role)
+    }
+
+    /// Hands the entity's sprite over to the named animation graph. Without such a graph
+    /// the entity is left as it was.
+    pub fn set_animation(
+        &mut self,
+        graphs: &[AnimationGraph],
+        name: &str,
+        sprites: &[SpriteData],
+    ) -> Result<(), String> {
+        let machine =
+            AnimationStateMachine::start(graphs, name, &mut self.sprite_animator, sprites)
+                .ok_or_else(|| format!("No animation named {}", name))?;
+        self.animation = Some(machine);
+        self.fit_hitbox_to_sprite(sprites);
+        Ok(())
     }
 
     pub fn get_bounds(&self) -> Bounds {
+        let feet = self.position + self.hitbox_offset;
         Bounds {
-            top_left: Vec2::new(
-                self.position.x - self.size.x / 2.0,
-                self.position.y - self.size.y,
-            ),
-            bottom_right: Vec2::new(self.position.x + self.size.x / 2.0, self.position.y),
+            top_left: Vec2::new(feet.x - self.size.x / 2.0, feet.y - self.size.y),
+            bottom_right: Vec2::new(feet.x + self.size.x / 2.0, feet.y),
+        }
+    }
+
+    /// Draws the sprite at `scale`, growing the collision bounds along with it.
+    pub fn set_scale(&mut self, scale: f32, sprites: &[SpriteData]) {
+        self.size *= scale / self.sprite_animator.scale;
+        self.sprite_animator.scale = scale;
+        self.fit_hitbox_to_sprite(sprites);
+    }
+
+    /// Takes the collision bounds from the sprite's hitbox on the current frame.
+    /// Sprites without one keep whatever size the entity was given.
+    pub fn fit_hitbox_to_sprite(&mut self, sprites: &[SpriteData]) {
+        let animator = &self.sprite_animator;
+        let sprite_data = &sprites[animator.sprite.index()];
+        let Some(hitbox) = sprite_data.hitbox(animator.current_frame) else {
+            return;
+        };
+        let anchor = sprite_data.anchor(animator.current_frame);
+        let hitbox_feet = hitbox.position.as_vec2() + hitbox.size.as_vec2() * Vec2::new(0.5, 1.0);
+        self.size = hitbox.size.as_vec2() * animator.scale;
+        self.hitbox_offset = (hitbox_feet - anchor) * animator.scale;
+        if animator.flip_x {
+            self.hitbox_offset.x = -self.hitbox_offset.x;
         }
     }
 }
@@ -0,0 +1,174 @@
+use std::{fmt, io, path::PathBuf};
+
+/// Everything that can go wrong loading sprites, with enough context to point at
+/// the file and the bit of it that is broken.
+#[derive(Debug)]
+pub enum AssetError {
+    /// every file a sprite needs that isn't there, gathered up front so one run shows them all
+    MissingFiles(Vec<PathBuf>),
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    /// the file isn't json at all
+    Json {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    /// a json value is missing or has the wrong type or range
+    InvalidField {
+        path: PathBuf,
+        /// the frame's key in a hash export, or its index in an array export
+        frame: Option<String>,
+        /// dotted path to the value, like `frame.x` or `meta.frameTags[0].to`
+        field: String,
+        cause: String,
+    },
+    /// a png, aseprite or pack file that couldn't be decoded, or a png that couldn't be encoded
+    Decode {
+        path: PathBuf,
+        cause: String,
+    },
+    /// a frame that can't go into the atlas
+    Atlas {
+        sprite: String,
+        frame: usize,
+        cause: String,
+    },
+    /// the gpu side, creating or uploading a texture
+    Texture {
+        path: Option<PathBuf>,
+        cause: String,
+    },
+}
+
+impl AssetError {
+    pub fn io(path: impl Into<PathBuf>, source: io::Error) -> Self {
+        AssetError::Io {
+            path: path.into(),
+            source,
+        }
+    }
+
+    pub fn decode(path: impl Into<PathBuf>, cause: impl fmt::Display) -> Self {
+        AssetError::Decode {
+            path: path.into(),
+            cause: cause.to_string(),
+        }
+    }
+
+    /// The file the error is about, if it is about just one.
+    pub fn path(&self) -> Option<&PathBuf> {
+        match self {
+            AssetError::MissingFiles(_) | AssetError::Atlas { .. } => None,
+            AssetError::Io { path, .. }
+            | AssetError::Json { path, .. }
+            | AssetError::InvalidField { path, .. }
+            | AssetError::Decode { path, .. } => Some(path),
+            AssetError::Texture { path, .. } => path.as_ref(),
+        }
+    }
+}
+
+impl fmt::Display for AssetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssetError::MissingFiles(files) => {
+                write!(f, "Missing files:")?;
+                for file in files {
+                    write!(f, "\n\t{}", file.display())?;
+                }
+                Ok(())
+            }
+            AssetError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            AssetError::Json { path, source } => {
+                write!(f, "{}: invalid json: {}", path.display(), source)
+            }
+            AssetError::InvalidField {
+                path,
+                frame,
+                field,
+                cause,
+            } => {
+                write!(f, "{}: ", path.display())?;
+                if let Some(frame) = frame {
+                    write!(f, "frame {}: ", frame)?;
+                }
+                write!(f, "{}: {}", field, cause)
+            }
+            AssetError::Decode { path, cause } => write!(f, "{}: {}", path.display(), cause),
+            AssetError::Atlas {
+                sprite,
+                frame,
+                cause,
+            } => write!(f, "frame {} of sprite {}: {}", frame, sprite, cause),
+            AssetError::Texture {
+                path: Some(path),
+                cause,
+            } => write!(f, "{}: {}", path.display(), cause),
+            AssetError::Texture { path: None, cause } => write!(f, "{}", cause),
+        }
+    }
+}
+
+impl std::error::Error for AssetError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AssetError::Io { source, .. } => Some(source),
+            AssetError::Json { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::{
+        sprite::load_sprite,
+        vfs::{temp_folder, Vfs},
+    };
+
+    #[test]
+    fn missing_sprites_list_the_files_they_need() {
+        let folder = temp_folder("error-missing");
+        let error = load_sprite(&Vfs::Folder(folder.clone()), "sprites", "apple").unwrap_err();
+        let AssetError::MissingFiles(files) = &error else {
+            panic!("expected missing files, got {}", error);
+        };
+        assert_eq!(
+            files,
+            &[
+                folder.join("sprites/apple.png"),
+                folder.join("sprites/apple.json")
+            ]
+        );
+        assert!(error.to_string().contains("apple.png"));
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn decode_errors_name_the_file() {
+        let folder = temp_folder("error-decode");
+        fs::create_dir_all(folder.join("sprites")).unwrap();
+        fs::copy(
+            "assets/sprites/apple.json",
+            folder.join("sprites/apple.json"),
+        )
+        .unwrap();
+        fs::write(folder.join("sprites/apple.png"), b"not a png").unwrap();
+
+        let error = load_sprite(&Vfs::Folder(folder.clone()), "sprites", "apple").unwrap_err();
+        assert!(matches!(error, AssetError::Decode { .. }), "{}", error);
+        assert_eq!(error.path(), Some(&folder.join("sprites/apple.png")));
+        let message = error.to_string();
+        assert!(
+            message.starts_with(&folder.join("sprites/apple.png").display().to_string()),
+            "{}",
+            message
+        );
+        fs::remove_dir_all(&folder).unwrap();
+    }
+}
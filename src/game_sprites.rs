@@ -0,0 +1,49 @@
+//! The sprites gameplay code spawns and switches to by name, looked up once at startup.
+//! Handles outlive reloads, so a sprite whose files go away later keeps its last frames
+//! instead of taking the game down, see `Graphics::reload`.
+
+use crate::{graphics::Graphics, sprite::Sprite};
+
+#[derive(Copy, Clone, Debug)]
+pub struct GameSprites {
+    pub reticle: Sprite,
+    pub apple: Sprite,
+    pub tree: Sprite,
+    pub tree_stump: Sprite,
+    pub log: Sprite,
+    pub man_idle: Sprite,
+    pub man_walk: Sprite,
+}
+
+impl GameSprites {
+    const NAMES: [&'static str; 7] = [
+        "reticle",
+        "apple",
+        "tree",
+        "tree_stump",
+        "log",
+        "man_idle",
+        "man_walk",
+    ];
+
+    /// Fails naming every sprite that isn't loaded.
+    pub fn lookup(graphics: &Graphics) -> Result<Self, String> {
+        let missing: Vec<&str> = Self::NAMES
+            .into_iter()
+            .filter(|name| graphics.sprite(name).is_none())
+            .collect();
+        if !missing.is_empty() {
+            return Err(format!("No sprites named {}", missing.join(", ")));
+        }
+        let sprite = |name: &str| graphics.sprite(name).expect("checked above");
+        Ok(Self {
+            reticle: sprite("reticle"),
+            apple: sprite("apple"),
+            tree: sprite("tree"),
+            tree_stump: sprite("tree_stump"),
+            log: sprite("log"),
+            man_idle: sprite("man_idle"),
+            man_walk: sprite("man_walk"),
+        })
+    }
+}
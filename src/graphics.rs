@@ -1,60 +1,249 @@
-use std::path::Path;
+use raylib::{
+    color::Color,
+    consts::TextureFilter,
+    texture::{Image, RaylibTexture2D, Texture2D},
+    RaylibHandle, RaylibThread,
+};
 
-use raylib::{texture::Texture2D, RaylibHandle, RaylibThread};
-use strum::{EnumCount, IntoEnumIterator};
+use crate::{
+    atlas::AtlasSettings,
+    error::AssetError,
+    image::RgbaImage,
+    loader::{load_sprites_cpu, reload_sprites_cpu, LoadProgress, LoadedSprites},
+    manifest::load_manifest,
+    sidecar::SpriteFilter,
+    sprite::{discover_sprites, Frame, Sprite, SpriteData, SpriteRegistry, SpriteSheet},
+    vfs::{join, Vfs},
+};
 
-use crate::sprite::{load_sprites, Sprite, SpriteData};
+/// Uploads pixels decoded on the CPU.
+fn texture_from_rgba(
+    rl: &mut RaylibHandle,
+    thread: &RaylibThread,
+    image: &RgbaImage,
+) -> Result<Texture2D, String> {
+    let blank = Image::gen_image_color(image.width as i32, image.height as i32, Color::BLANK);
+    let mut texture = rl
+        .load_texture_from_image(thread, &blank)
+        .map_err(|e| format!("failed to create texture: {}", e))?;
+    texture
+        .update_texture(&image.pixels)
+        .map_err(|e| format!("failed to upload texture: {}", e))?;
+    Ok(texture)
+}
 
-fn load_textures(
+/// Uploads packed atlas pages.
+fn load_atlas_pages(
     rl: &mut RaylibHandle,
     thread: &RaylibThread,
-    asset_folder: &str,
-) -> Result<Vec<Texture2D>, String> {
-    let mut textures = Vec::with_capacity(Sprite::COUNT);
-    for sprite in Sprite::iter() {
-        let filename = sprite.to_filename();
-        let png_path = Path::new(asset_folder).join(format!("{}.png", filename));
-        let texture = rl
-            .load_texture(thread, png_path.to_str().unwrap())
-            .map_err(|e| format!("Failed to load texture {}: {}", filename, e))?;
+    pages: &[RgbaImage],
+) -> Result<Vec<Texture2D>, AssetError> {
+    let mut textures = Vec::with_capacity(pages.len());
+    for (i, page) in pages.iter().enumerate() {
+        let texture = texture_from_rgba(rl, thread, page).map_err(|e| AssetError::Texture {
+            path: None,
+            cause: format!("atlas page {}: {}", i, e),
+        })?;
         textures.push(texture);
     }
     Ok(textures)
 }
 
+/// Samples each page the way the sprites on it asked for, the atlas keeps filters
+/// on separate pages.
+fn set_page_filters(thread: &RaylibThread, pages: &[Texture2D], sprites: &[SpriteData]) {
+    let mut filters = vec![SpriteFilter::default(); pages.len()];
+    for data in sprites {
+        let frames = data
+            .frames
+            .iter()
+            .chain(data.palette_frames.iter().flatten());
+        for frame in frames {
+            if let Some(filter) = filters.get_mut(frame.page) {
+                *filter = data.defaults.filter;
+            }
+        }
+    }
+    for (page, filter) in pages.iter().zip(filters) {
+        let mode = match filter {
+            SpriteFilter::Point => TextureFilter::TEXTURE_FILTER_POINT,
+            SpriteFilter::Bilinear => TextureFilter::TEXTURE_FILTER_BILINEAR,
+        };
+        page.set_texture_filter(thread, mode);
+    }
+}
+
 pub struct Graphics {
+    pub registry: SpriteRegistry,
+    /// indexed by sprite handle
     pub sprites: Vec<SpriteData>,
-    pub textures: Vec<Texture2D>,
+    /// atlas pages, frames say which one they are on
+    pub pages: Vec<Texture2D>,
+    pub atlas_settings: AtlasSettings,
+    /// unpacked sheets, indexed by sprite handle, kept so a few sprites can be
+    /// reloaded and everything repacked without reading every file again.
+    /// Empty when loaded from compiled assets.
+    sources: Vec<SpriteSheet>,
 }
 
 impl Graphics {
     pub fn new(
         rl: &mut RaylibHandle,
         thread: &RaylibThread,
+        vfs: &Vfs,
         asset_folder: &str,
-    ) -> Result<Self, String> {
-        let sprites = load_sprites(asset_folder)?;
-        let textures = load_textures(rl, thread, asset_folder)?;
-        Ok(Self { sprites, textures })
+        atlas_settings: AtlasSettings,
+    ) -> Result<Self, AssetError> {
+        let loaded = load_sprites_cpu(
+            vfs,
+            asset_folder,
+            SpriteRegistry::new(),
+            atlas_settings,
+            &LoadProgress::default(),
+        )?;
+        Self::from_loaded(rl, thread, loaded)
+    }
+
+    /// Uploads what a `SpriteLoader` produced, the only part of loading that has to
+    /// happen on the raylib thread.
+    pub fn from_loaded(
+        rl: &mut RaylibHandle,
+        thread: &RaylibThread,
+        loaded: LoadedSprites,
+    ) -> Result<Self, AssetError> {
+        let pages = load_atlas_pages(rl, thread, &loaded.pages)?;
+        set_page_filters(thread, &pages, &loaded.sprites);
+        Ok(Self {
+            registry: loaded.registry,
+            sprites: loaded.sprites,
+            pages,
+            atlas_settings: loaded.atlas_settings,
+            sources: loaded.sources,
+        })
+    }
+
+    /// Loads the pages and manifest written by the `assets build` tool, nothing gets
+    /// parsed or packed here.
+    pub fn from_compiled(
+        rl: &mut RaylibHandle,
+        thread: &RaylibThread,
+        vfs: &Vfs,
+        compiled_folder: &str,
+    ) -> Result<Self, AssetError> {
+        let compiled = load_manifest(vfs, compiled_folder)?;
+        let mut pages = Vec::with_capacity(compiled.pages.len());
+        for (file_name, size) in compiled.pages.iter().zip(compiled.page_sizes.iter()) {
+            let page_path = join(compiled_folder, file_name);
+            let path = vfs.full_path(&page_path);
+            let bytes = vfs.read(&page_path)?;
+            let texture = Image::load_image_from_mem(".png", &bytes)
+                .and_then(|image| rl.load_texture_from_image(thread, &image))
+                .map_err(|e| AssetError::Texture {
+                    path: Some(path.clone()),
+                    cause: e.to_string(),
+                })?;
+            if texture.width() as u32 != size.x || texture.height() as u32 != size.y {
+                return Err(AssetError::Texture {
+                    path: Some(path),
+                    cause: format!(
+                        "page is {}x{} but the manifest says {}x{}, rebuild the assets",
+                        texture.width(),
+                        texture.height(),
+                        size.x,
+                        size.y
+                    ),
+                });
+            }
+            pages.push(texture);
+        }
+        set_page_filters(thread, &pages, &compiled.sprites);
+        Ok(Self {
+            registry: compiled.registry,
+            sprites: compiled.sprites,
+            pages,
+            atlas_settings: compiled.atlas_settings,
+            sources: vec![],
+        })
     }
 
+    /// Loads every sprite again. All or nothing, if anything fails the old sprites and
+    /// textures are kept as they were. Animators should be refreshed afterwards, see
+    /// `SpriteAnimator::refresh`.
+    ///
+    /// Sprites whose files are all gone keep their last data, like `reload_sprites`.
+    /// Not when loaded from compiled assets, there are no pixels to keep, so every
+    /// sprite needs its files.
     pub fn reload(
         &mut self,
         rl: &mut RaylibHandle,
         thread: &RaylibThread,
+        vfs: &Vfs,
         asset_folder: &str,
-    ) -> Result<(), String> {
-        self.sprites = load_sprites(asset_folder)?;
+    ) -> Result<(), AssetError> {
+        if self.sources.len() == self.registry.len() {
+            let mut names: Vec<String> = self
+                .registry
+                .iter()
+                .map(|(_, name)| name.to_string())
+                .collect();
+            names.extend(discover_sprites(vfs, asset_folder)?);
+            names.sort();
+            names.dedup();
+            return self.reload_sprites(rl, thread, vfs, asset_folder, &names);
+        }
+
+        // new sprites only get handles once they have data behind them
+        let loaded = load_sprites_cpu(
+            vfs,
+            asset_folder,
+            self.registry.clone(),
+            self.atlas_settings,
+            &LoadProgress::default(),
+        )?;
         // The old textures will be automatically unloaded when replaced
-        self.textures = load_textures(rl, thread, asset_folder)?;
+        *self = Self::from_loaded(rl, thread, loaded)?;
         Ok(())
     }
 
-    pub fn get_sprite_texture(&self, sprite: Sprite) -> &Texture2D {
-        &self.textures[sprite as usize]
+    /// Reads just the named sprites again and repacks the atlas, all or nothing like `reload`.
+    /// Sprites whose files are all gone keep their last data, handles to them are still around.
+    pub fn reload_sprites(
+        &mut self,
+        rl: &mut RaylibHandle,
+        thread: &RaylibThread,
+        vfs: &Vfs,
+        asset_folder: &str,
+        names: &[String],
+    ) -> Result<(), AssetError> {
+        if self.sources.len() != self.registry.len() {
+            // loaded from compiled assets, there are no sheets to patch
+            return self.reload(rl, thread, vfs, asset_folder);
+        }
+
+        let loaded = reload_sprites_cpu(
+            vfs,
+            asset_folder,
+            names,
+            &self.registry,
+            &self.sources,
+            self.atlas_settings,
+        )?;
+        *self = Self::from_loaded(rl, thread, loaded)?;
+        Ok(())
+    }
+
+    /// Looks up a sprite by file name, without the extension. Look sprites up once and
+    /// keep the handle, see `GameSprites`.
+    pub fn sprite(&self, name: &str) -> Option<Sprite> {
+        self.registry.lookup(name)
+    }
+
+    /// The atlas page a frame is drawn from.
+    pub fn get_frame_texture(&self, frame: &Frame) -> &Texture2D {
+        &self.pages[frame.page]
     }
 
     pub fn get_sprite_data(&self, sprite: Sprite) -> &SpriteData {
-        &self.sprites[sprite as usize]
+        &self.sprites[sprite.index()]
     }
 }
@@ -0,0 +1,131 @@
+use std::{fs::File, io::BufWriter, path::Path};
+
+use glam::UVec2;
+use png::{BitDepth, ColorType, Transformations};
+
+use crate::error::AssetError;
+
+/// Tightly packed 8 bit RGBA pixels, row by row from the top left.
+#[derive(Clone, Debug)]
+pub struct RgbaImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl RgbaImage {
+    /// A fully transparent image.
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; (width * height * 4) as usize],
+        }
+    }
+
+    pub fn size(&self) -> UVec2 {
+        UVec2::new(self.width, self.height)
+    }
+
+    fn offset(&self, x: u32, y: u32) -> usize {
+        ((y * self.width + x) * 4) as usize
+    }
+
+    pub fn get_pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let i = self.offset(x, y);
+        [
+            self.pixels[i],
+            self.pixels[i + 1],
+            self.pixels[i + 2],
+            self.pixels[i + 3],
+        ]
+    }
+
+    pub fn put_pixel(&mut self, x: u32, y: u32, color: [u8; 4]) {
+        let i = self.offset(x, y);
+        self.pixels[i..i + 4].copy_from_slice(&color);
+    }
+
+    /// Draws `color` over the existing pixel with normal alpha blending.
+    /// `opacity` scales the alpha of `color` first, like a layer opacity slider.
+    pub fn blend_pixel(&mut self, x: u32, y: u32, color: [u8; 4], opacity: u8) {
+        let src_a = color[3] as u32 * opacity as u32 / 255;
+        if src_a == 0 {
+            return;
+        }
+        let dst = self.get_pixel(x, y);
+        if src_a == 255 || dst[3] == 0 {
+            self.put_pixel(x, y, [color[0], color[1], color[2], src_a as u8]);
+            return;
+        }
+
+        // src over dst, kept in straight (not premultiplied) alpha
+        let dst_a = dst[3] as u32 * (255 - src_a) / 255;
+        let out_a = src_a + dst_a;
+        let mut out = [0, 0, 0, out_a as u8];
+        for c in 0..3 {
+            out[c] = ((color[c] as u32 * src_a + dst[c] as u32 * dst_a) / out_a) as u8;
+        }
+        self.put_pixel(x, y, out);
+    }
+}
+
+/// Decodes a png of any color type into RGBA, `path` is where the bytes came from.
+pub fn decode_png(bytes: &[u8], path: &Path) -> Result<RgbaImage, AssetError> {
+    let mut decoder = png::Decoder::new(bytes);
+    // palettes and low bit depths come out as 8 bit gray or rgb(a)
+    decoder.set_transformations(Transformations::normalize_to_color8());
+    let mut reader = decoder
+        .read_info()
+        .map_err(|e| AssetError::decode(path, e))?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader
+        .next_frame(&mut buffer)
+        .map_err(|e| AssetError::decode(path, e))?;
+    buffer.truncate(info.buffer_size());
+
+    let pixels = match info.color_type {
+        ColorType::Rgba => buffer,
+        ColorType::Rgb => buffer
+            .chunks_exact(3)
+            .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 255])
+            .collect(),
+        ColorType::GrayscaleAlpha => buffer
+            .chunks_exact(2)
+            .flat_map(|ga| [ga[0], ga[0], ga[0], ga[1]])
+            .collect(),
+        ColorType::Grayscale => buffer.iter().flat_map(|&g| [g, g, g, 255]).collect(),
+        ColorType::Indexed => {
+            return Err(AssetError::decode(path, "failed to expand palette"));
+        }
+    };
+
+    Ok(RgbaImage {
+        width: info.width,
+        height: info.height,
+        pixels,
+    })
+}
+
+/// Width and height from the png header, without decoding the pixels.
+pub fn read_png_size(bytes: &[u8], path: &Path) -> Result<UVec2, AssetError> {
+    let reader = png::Decoder::new(bytes)
+        .read_info()
+        .map_err(|e| AssetError::decode(path, e))?;
+    let info = reader.info();
+    Ok(UVec2::new(info.width, info.height))
+}
+
+/// Writes the image as an 8 bit RGBA png.
+pub fn encode_png(image: &RgbaImage, path: &Path) -> Result<(), AssetError> {
+    let file = File::create(path).map_err(|e| AssetError::io(path, e))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), image.width, image.height);
+    encoder.set_color(ColorType::Rgba);
+    encoder.set_depth(BitDepth::Eight);
+    let mut writer = encoder
+        .write_header()
+        .map_err(|e| AssetError::decode(path, e))?;
+    writer
+        .write_image_data(&image.pixels)
+        .map_err(|e| AssetError::decode(path, e))
+}
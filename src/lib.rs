@@ -0,0 +1,22 @@
+pub mod animation;
+pub mod aseprite;
+pub mod atlas;
+pub mod audio;
+pub mod draw;
+pub mod entity;
+pub mod error;
+pub mod game_sprites;
+pub mod graphics;
+pub mod image;
+pub mod lint;
+pub mod loader;
+pub mod manifest;
+pub mod pack;
+pub mod palette;
+pub mod settings;
+pub mod sidecar;
+pub mod sprite;
+pub mod state;
+pub mod step;
+pub mod vfs;
+pub mod watcher;
@@ -0,0 +1,501 @@
+//! Checks the asset folders for art, audio and animation graphs that would load wrong
+//! or not at all, used by `assets check`.
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use glam::UVec2;
+use strum::IntoEnumIterator;
+
+use crate::{
+    animation::load_animation_graph,
+    aseprite::AsepriteFile,
+    audio::{get_song_file_name, get_sound_file_name, Song, SoundEffect},
+    error::AssetError,
+    image::read_png_size,
+    palette::load_palettes,
+    settings::{ANIMATIONS_FOLDER, MUSIC_FOLDER, SOUNDS_FOLDER, SPRITES_FOLDER},
+    sidecar::{load_frame_events, load_sprite_defaults},
+    sprite::{
+        discover_sprites, sprite_data_from_json, sprite_name_of, SpriteData, SpriteRegistry,
+        SIDECAR_SUFFIX,
+    },
+    vfs::{join, Vfs},
+};
+
+/// Something wrong with one file.
+#[derive(Debug)]
+pub struct LintProblem {
+    pub path: PathBuf,
+    pub message: String,
+}
+
+impl fmt::Display for LintProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.message)
+    }
+}
+
+/// Runs every check over the sprite, sound, music and animation folders.
+pub fn check_assets(vfs: &Vfs) -> Vec<LintProblem> {
+    let mut problems = check_sprites(vfs, SPRITES_FOLDER);
+    let sound_names: Vec<&str> = SoundEffect::iter().map(get_sound_file_name).collect();
+    problems.extend(check_audio(vfs, SOUNDS_FOLDER, &sound_names));
+    let song_names: Vec<&str> = Song::iter().map(get_song_file_name).collect();
+    problems.extend(check_audio(vfs, MUSIC_FOLDER, &song_names));
+    problems.extend(check_animations(vfs, ANIMATIONS_FOLDER, SPRITES_FOLDER));
+    problems
+}
+
+struct Lint<'a> {
+    vfs: &'a Vfs,
+    problems: Vec<LintProblem>,
+}
+
+impl Lint<'_> {
+    fn report(&mut self, path: &str, message: impl Into<String>) {
+        self.problems.push(LintProblem {
+            path: self.vfs.full_path(path),
+            message: message.into(),
+        });
+    }
+
+    fn report_error(&mut self, error: AssetError) {
+        self.problems.push(LintProblem {
+            path: error.path().cloned().unwrap_or_default(),
+            message: error.to_string(),
+        });
+    }
+}
+
+/// Sprites are found by file name, so every png, json and aseprite file makes one.
+/// Anything else in the folder is never loaded, apart from sidecars.
+pub fn check_sprites(vfs: &Vfs, sprites_folder: &str) -> Vec<LintProblem> {
+    let mut lint = Lint {
+        vfs,
+        problems: vec![],
+    };
+
+    let mut sidecars = vec![];
+    match vfs.list(sprites_folder) {
+        Ok(file_names) => {
+            for file_name in file_names {
+                let path = join(sprites_folder, &file_name);
+                if sprite_name_of(Path::new(&file_name)).is_none() {
+                    lint.report(&path, "not part of any sprite");
+                } else if file_name.ends_with(SIDECAR_SUFFIX) {
+                    sidecars.push(path);
+                }
+            }
+        }
+        Err(e) => {
+            lint.report_error(e);
+            return lint.problems;
+        }
+    }
+
+    let names = match discover_sprites(vfs, sprites_folder) {
+        Ok(names) => names,
+        Err(e) => {
+            lint.report_error(e);
+            return lint.problems;
+        }
+    };
+    for sidecar_path in sidecars.iter() {
+        let name = sprite_name_of(Path::new(sidecar_path)).unwrap_or_default();
+        if !names.iter().any(|sprite| sprite == name) {
+            lint.report(sidecar_path, "is a sidecar for a sprite that doesn't exist");
+        }
+    }
+
+    for name in names {
+        let sidecar_path = join(sprites_folder, &format!("{}{}", name, SIDECAR_SUFFIX));
+        if vfs.exists(&sidecar_path) {
+            let sidecar = vfs.read_json(&sidecar_path).and_then(|sidecar| {
+                let path = vfs.full_path(&sidecar_path);
+                load_sprite_defaults(&sidecar, &path)?;
+                load_frame_events(&sidecar, &path)?;
+                load_palettes(&sidecar, &path)
+            });
+            if let Err(e) = sidecar {
+                lint.report_error(e);
+            }
+        }
+
+        let png_path = join(sprites_folder, &format!("{}.png", name));
+        let json_path = join(sprites_folder, &format!("{}.json", name));
+        let aseprite_path = join(sprites_folder, &format!("{}.aseprite", name));
+        // the layer the sprite's art is loaded from, see `load_sprite`
+        let art = vfs.layer_with(&[&png_path, &json_path, &aseprite_path]);
+
+        match (art.exists(&png_path), art.exists(&json_path)) {
+            (true, true) => check_export(&mut lint, art, &name, &png_path, &json_path),
+            (true, false) => lint.report(&png_path, "has no json next to it"),
+            (false, true) => lint.report(&json_path, "has no png next to it"),
+            (false, false) => {}
+        }
+
+        if art.exists(&aseprite_path) {
+            if let Some(source_time) = art.modified(&aseprite_path) {
+                for export in [&png_path, &json_path] {
+                    if art.modified(export).is_some_and(|time| time < source_time) {
+                        lint.report(export, "is older than the .aseprite source, re-export it");
+                    }
+                }
+            }
+            // only used when there is no export, but it should still open
+            match AsepriteFile::load(art, &aseprite_path) {
+                Ok(file) => check_frames(&mut lint, &aseprite_path, &file.sprite_data()),
+                Err(e) => lint.report_error(e),
+            }
+        }
+    }
+    lint.problems
+}
+
+/// Checks an exported png + json pair against each other.
+fn check_export(lint: &mut Lint, vfs: &Vfs, name: &str, png_path: &str, json_path: &str) {
+    let json = match vfs.read_json(json_path) {
+        Ok(json) => json,
+        Err(e) => {
+            lint.report_error(e);
+            return;
+        }
+    };
+    let data = match sprite_data_from_json(&json, &vfs.full_path(json_path)) {
+        Ok(data) => data,
+        Err(e) => {
+            lint.report_error(e);
+            return;
+        }
+    };
+    check_frames(lint, json_path, &data);
+
+    let expected_image = format!("{}.png", name);
+    match json["meta"]["image"].as_str() {
+        Some(image) if image == expected_image => {}
+        Some(image) => lint.report(
+            json_path,
+            format!("meta.image is {} but should be {}", image, expected_image),
+        ),
+        None => lint.report(json_path, "meta.image is missing"),
+    }
+
+    let meta_size = &json["meta"]["size"];
+    let sheet_size = match (meta_size["w"].as_u64(), meta_size["h"].as_u64()) {
+        (Some(w), Some(h)) => Some(UVec2::new(w as u32, h as u32)),
+        _ => {
+            lint.report(json_path, "meta.size is missing");
+            None
+        }
+    };
+
+    if let Some(sheet_size) = sheet_size {
+        for (i, frame) in data.frames.iter().enumerate() {
+            let end = frame.sample_position + frame.sheet_size();
+            if end.x > sheet_size.x || end.y > sheet_size.y {
+                lint.report(
+                    json_path,
+                    format!(
+                        "frame {} reaches {}x{}, outside the {}x{} meta.size",
+                        i, end.x, end.y, sheet_size.x, sheet_size.y
+                    ),
+                );
+            }
+        }
+        let png_size = vfs
+            .read(png_path)
+            .and_then(|bytes| read_png_size(&bytes, &vfs.full_path(png_path)));
+        match png_size {
+            Ok(png_size) if png_size != sheet_size => lint.report(
+                png_path,
+                format!(
+                    "is {}x{} but the json meta.size says {}x{}",
+                    png_size.x, png_size.y, sheet_size.x, sheet_size.y
+                ),
+            ),
+            Ok(_) => {}
+            Err(e) => lint.report_error(e),
+        }
+    }
+}
+
+/// Checks that hold for sprites from either source.
+fn check_frames(lint: &mut Lint, path: &str, data: &SpriteData) {
+    let first_size = data.frames[0].source_size;
+    for (i, frame) in data.frames.iter().enumerate() {
+        if frame.source_size != first_size {
+            lint.report(
+                path,
+                format!(
+                    "frame {} is {}x{} but frame 0 is {}x{}",
+                    i, frame.source_size.x, frame.source_size.y, first_size.x, first_size.y
+                ),
+            );
+        }
+        if frame.duration <= 0.0 {
+            lint.report(
+                path,
+                format!("frame {} has a duration of {}ms", i, frame.duration),
+            );
+        }
+    }
+}
+
+/// Every name should have an ogg, and every ogg should have a name.
+pub fn check_audio(vfs: &Vfs, folder: &str, names: &[&str]) -> Vec<LintProblem> {
+    let mut lint = Lint {
+        vfs,
+        problems: vec![],
+    };
+
+    for name in names {
+        let path = join(folder, &format!("{}.ogg", name));
+        if !vfs.exists(&path) {
+            lint.report(&path, "is missing");
+        }
+    }
+
+    match vfs.list(folder) {
+        Ok(file_names) => {
+            for file_name in file_names {
+                let is_used = file_name
+                    .strip_suffix(".ogg")
+                    .is_some_and(|stem| names.contains(&stem));
+                if !is_used {
+                    lint.report(
+                        &join(folder, &file_name),
+                        "is not used by any Song or SoundEffect",
+                    );
+                }
+            }
+        }
+        Err(e) => lint.report_error(e),
+    }
+    lint.problems
+}
+
+/// Every graph has to load, with the sprites it names in `sprites_folder`.
+pub fn check_animations(vfs: &Vfs, folder: &str, sprites_folder: &str) -> Vec<LintProblem> {
+    let mut lint = Lint {
+        vfs,
+        problems: vec![],
+    };
+
+    let mut registry = SpriteRegistry::new();
+    match discover_sprites(vfs, sprites_folder) {
+        Ok(names) => {
+            for name in names {
+                registry.register(&name);
+            }
+        }
+        // already reported by `check_sprites`
+        Err(_) => return lint.problems,
+    }
+
+    let file_names = match vfs.list(folder) {
+        Ok(file_names) => file_names,
+        Err(e) => {
+            lint.report_error(e);
+            return lint.problems;
+        }
+    };
+    for file_name in file_names {
+        let path = join(folder, &file_name);
+        let Some(name) = file_name.strip_suffix(".json") else {
+            lint.report(&path, "is not a json animation graph, it is never loaded");
+            continue;
+        };
+        let graph = vfs
+            .read_json(&path)
+            .and_then(|json| load_animation_graph(&json, name, &vfs.full_path(&path), &registry));
+        if let Err(e) = graph {
+            lint.report_error(e);
+        }
+    }
+    lint.problems
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{self, File},
+        time::{Duration, SystemTime},
+    };
+
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::vfs::temp_folder;
+
+    /// A temp asset folder with the apple's png and json, the json changed by `edit`.
+    fn apple_folder(name: &str, edit: impl FnOnce(&mut Value)) -> PathBuf {
+        let folder = temp_folder(name);
+        let sprites = folder.join(SPRITES_FOLDER);
+        fs::create_dir_all(&sprites).unwrap();
+        fs::copy("assets/sprites/apple.png", sprites.join("apple.png")).unwrap();
+        let mut json: Value =
+            serde_json::from_slice(&fs::read("assets/sprites/apple.json").unwrap()).unwrap();
+        edit(&mut json);
+        fs::write(sprites.join("apple.json"), json.to_string()).unwrap();
+        folder
+    }
+
+    fn sprite_problems(folder: &Path) -> Vec<LintProblem> {
+        check_sprites(&Vfs::Folder(folder.to_path_buf()), SPRITES_FOLDER)
+    }
+
+    /// Fails unless a problem with `file` says something containing `message`.
+    fn assert_reported(problems: &[LintProblem], file: &str, message: &str) {
+        assert!(
+            problems
+                .iter()
+                .any(|problem| problem.path.ends_with(file) && problem.message.contains(message)),
+            "no {}: {} in {:#?}",
+            file,
+            message,
+            problems
+        );
+    }
+
+    #[test]
+    fn the_game_assets_are_clean() {
+        let problems = check_assets(&Vfs::Folder("assets".into()));
+        assert!(problems.is_empty(), "{:#?}", problems);
+
+        let folder = apple_folder("lint-clean", |_| {});
+        assert!(sprite_problems(&folder).is_empty());
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn reports_stray_files_and_sidecars() {
+        let folder = apple_folder("lint-stray", |_| {});
+        let sprites = folder.join(SPRITES_FOLDER);
+        fs::write(sprites.join("notes.txt"), "").unwrap();
+        fs::write(sprites.join("pear.sprite.json"), "{}").unwrap();
+        fs::write(sprites.join("apple.sprite.json"), r#"{ "scale": 0 }"#).unwrap();
+
+        let problems = sprite_problems(&folder);
+        assert_reported(&problems, "notes.txt", "not part of any sprite");
+        assert_reported(&problems, "pear.sprite.json", "sprite that doesn't exist");
+        assert_reported(&problems, "apple.sprite.json", "scale");
+        assert_eq!(problems.len(), 3, "{:#?}", problems);
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn reports_exports_missing_a_half() {
+        let folder = apple_folder("lint-halves", |_| {});
+        let sprites = folder.join(SPRITES_FOLDER);
+        fs::rename(sprites.join("apple.json"), sprites.join("pear.json")).unwrap();
+
+        let problems = sprite_problems(&folder);
+        assert_reported(&problems, "apple.png", "has no json next to it");
+        assert_reported(&problems, "pear.json", "has no png next to it");
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    /// Lints the apple with its json changed by `edit`.
+    fn assert_export_reported(edit: impl FnOnce(&mut Value), file: &str, message: &str) {
+        let folder = apple_folder("lint-export", edit);
+        assert_reported(&sprite_problems(&folder), file, message);
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn reports_exports_that_disagree_with_their_png() {
+        assert_export_reported(
+            |json| json["meta"]["image"] = json!("pear.png"),
+            "apple.json",
+            "meta.image is pear.png",
+        );
+        assert_export_reported(
+            |json| json["meta"]["size"] = Value::Null,
+            "apple.json",
+            "meta.size is missing",
+        );
+        let narrow = |json: &mut Value| json["meta"]["size"]["w"] = json!(8);
+        assert_export_reported(narrow, "apple.json", "frame 1 reaches 16x8");
+        assert_export_reported(
+            narrow,
+            "apple.png",
+            "is 16x8 but the json meta.size says 8x8",
+        );
+        assert_export_reported(
+            |json| json["frames"]["apple 1.aseprite"]["sourceSize"]["h"] = json!(16),
+            "apple.json",
+            "frame 1 is 8x16 but frame 0 is 8x8",
+        );
+        assert_export_reported(
+            |json| json["frames"]["apple 1.aseprite"]["duration"] = json!(0),
+            "apple.json",
+            "frame 1 has a duration of 0ms",
+        );
+    }
+
+    #[test]
+    fn reports_stale_and_broken_aseprite_files() {
+        let folder = apple_folder("lint-aseprite", |_| {});
+        let sprites = folder.join(SPRITES_FOLDER);
+        fs::copy(
+            "assets/sprites/apple.aseprite",
+            sprites.join("apple.aseprite"),
+        )
+        .unwrap();
+        let long_ago = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        for export in ["apple.png", "apple.json"] {
+            let file = File::options()
+                .write(true)
+                .open(sprites.join(export))
+                .unwrap();
+            file.set_modified(long_ago).unwrap();
+        }
+
+        let problems = sprite_problems(&folder);
+        assert_reported(&problems, "apple.png", "older than the .aseprite source");
+        assert_reported(&problems, "apple.json", "older than the .aseprite source");
+
+        fs::write(sprites.join("apple.aseprite"), b"not an aseprite file").unwrap();
+        assert_reported(&sprite_problems(&folder), "apple.aseprite", "");
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn reports_missing_and_unused_audio() {
+        let folder = temp_folder("lint-audio");
+        fs::create_dir_all(folder.join("sounds")).unwrap();
+        for file_name in ["step.ogg", "old_step.ogg", "step.wav"] {
+            fs::write(folder.join("sounds").join(file_name), "").unwrap();
+        }
+
+        let problems = check_audio(&Vfs::Folder(folder.clone()), "sounds", &["step", "jump"]);
+        assert_reported(&problems, "jump.ogg", "is missing");
+        assert_reported(&problems, "old_step.ogg", "not used");
+        assert_reported(&problems, "step.wav", "not used");
+        assert_eq!(problems.len(), 3, "{:#?}", problems);
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn reports_graphs_that_dont_load() {
+        let folder = apple_folder("lint-animations", |_| {});
+        let animations = folder.join(ANIMATIONS_FOLDER);
+        fs::create_dir_all(&animations).unwrap();
+        let graph = |sprite: &str| {
+            json!({ "initial": "idle", "states": { "idle": { "sprite": sprite } } }).to_string()
+        };
+        fs::write(animations.join("apple.json"), graph("apple")).unwrap();
+        fs::write(animations.join("pear.json"), graph("pear")).unwrap();
+        fs::write(animations.join("notes.txt"), "").unwrap();
+
+        let vfs = Vfs::Folder(folder.clone());
+        let problems = check_animations(&vfs, ANIMATIONS_FOLDER, SPRITES_FOLDER);
+        assert_reported(&problems, "pear.json", "sprite");
+        assert_reported(&problems, "notes.txt", "never loaded");
+        assert_eq!(problems.len(), 2, "{:#?}", problems);
+        fs::remove_dir_all(&folder).unwrap();
+    }
+}
@@ -0,0 +1,338 @@
+//! The cpu half of loading sprites: decoding pngs, parsing json and packing the atlas.
+//! None of it needs a window, so it runs on worker threads while the main thread draws
+//! a loading screen, then `Graphics::from_loaded` uploads the result.
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+};
+
+use crate::{
+    atlas::{pack_atlas, AtlasSettings},
+    error::AssetError,
+    image::RgbaImage,
+    sprite::{load_sprite, register_sprites, SpriteData, SpriteRegistry, SpriteSheet},
+    vfs::{join, Vfs},
+};
+
+/// Everything needed to make a `Graphics`, still on the cpu.
+#[derive(Debug)]
+pub struct LoadedSprites {
+    pub registry: SpriteRegistry,
+    /// unpacked sheets, indexed by sprite handle
+    pub sources: Vec<SpriteSheet>,
+    /// packed frame data, indexed by sprite handle
+    pub sprites: Vec<SpriteData>,
+    pub pages: Vec<RgbaImage>,
+    pub atlas_settings: AtlasSettings,
+}
+
+/// How far along a load is, shared with the threads doing it.
+#[derive(Debug, Default)]
+pub struct LoadProgress {
+    done: AtomicUsize,
+    /// zero until the sprites have been found
+    total: AtomicUsize,
+}
+
+impl LoadProgress {
+    /// From 0 to 1.
+    pub fn fraction(&self) -> f32 {
+        let total = self.total.load(Ordering::Relaxed);
+        if total == 0 {
+            return 0.0;
+        }
+        self.done.load(Ordering::Relaxed) as f32 / total as f32
+    }
+
+    fn start(&self, total: usize) {
+        self.done.store(0, Ordering::Relaxed);
+        self.total.store(total, Ordering::Relaxed);
+    }
+
+    fn step(&self) {
+        self.done.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Packs unpacked sheets into atlas pages, the sheets are indexed by handle in `registry`.
+pub fn pack_sprites(
+    registry: SpriteRegistry,
+    sources: Vec<SpriteSheet>,
+    atlas_settings: AtlasSettings,
+) -> Result<LoadedSprites, AssetError> {
+    let mut sheets = sources.clone();
+    let pages = pack_atlas(&mut sheets, &atlas_settings)?;
+    Ok(LoadedSprites {
+        registry,
+        sources,
+        sprites: sheets.into_iter().map(|sheet| sheet.data).collect(),
+        pages,
+        atlas_settings,
+    })
+}
+
+/// Reads just the named sprites again on top of `registry` and `sources`, then repacks the
+/// atlas. Sprites whose files are all gone keep their last sheet. The originals are left
+/// alone, so if anything fails whatever was loaded before still stands.
+pub fn reload_sprites_cpu(
+    vfs: &Vfs,
+    asset_folder: &str,
+    names: &[String],
+    registry: &SpriteRegistry,
+    sources: &[SpriteSheet],
+    atlas_settings: AtlasSettings,
+) -> Result<LoadedSprites, AssetError> {
+    let mut registry = registry.clone();
+    let mut sources = sources.to_vec();
+    for name in names {
+        let has_files = ["png", "json", "aseprite"]
+            .iter()
+            .any(|extension| vfs.exists(&join(asset_folder, &format!("{}.{}", name, extension))));
+        if !has_files {
+            continue;
+        }
+        let sheet = load_sprite(vfs, asset_folder, name)?;
+        let sprite = registry.register(name);
+        if sprite.index() == sources.len() {
+            sources.push(sheet);
+        } else {
+            sources[sprite.index()] = sheet;
+        }
+    }
+    pack_sprites(registry, sources, atlas_settings)
+}
+
+/// Loads and packs every sprite in the folder, decoding them across a few threads.
+/// Blocks until done, use `SpriteLoader` to do it in the background.
+pub fn load_sprites_cpu(
+    vfs: &Vfs,
+    asset_folder: &str,
+    mut registry: SpriteRegistry,
+    atlas_settings: AtlasSettings,
+    progress: &LoadProgress,
+) -> Result<LoadedSprites, AssetError> {
+    register_sprites(vfs, asset_folder, &mut registry)?;
+    let names: Vec<&str> = registry.iter().map(|(_, name)| name).collect();
+    // one step per sprite and one for packing
+    progress.start(names.len() + 1);
+
+    let num_workers = thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(names.len())
+        .max(1);
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<(usize, Result<SpriteSheet, AssetError>)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..num_workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut loaded = vec![];
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(name) = names.get(i) else {
+                            break;
+                        };
+                        loaded.push((i, load_sprite(vfs, asset_folder, name)));
+                        progress.step();
+                    }
+                    loaded
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("Sprite loading thread panicked"))
+            .collect()
+    });
+    // back into handle order, so the first broken sprite is the one reported
+    results.sort_by_key(|(i, _)| *i);
+    let sources = results
+        .into_iter()
+        .map(|(_, sheet)| sheet)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let loaded = pack_sprites(registry, sources, atlas_settings)?;
+    progress.step();
+    Ok(loaded)
+}
+
+/// Runs `load_sprites_cpu` on its own thread.
+pub struct SpriteLoader {
+    progress: Arc<LoadProgress>,
+    handle: JoinHandle<Result<LoadedSprites, AssetError>>,
+}
+
+impl SpriteLoader {
+    pub fn start(
+        vfs: &Vfs,
+        asset_folder: &str,
+        registry: SpriteRegistry,
+        atlas_settings: AtlasSettings,
+    ) -> Self {
+        let progress = Arc::new(LoadProgress::default());
+        let vfs = vfs.clone();
+        let asset_folder = asset_folder.to_string();
+        let thread_progress = progress.clone();
+        let handle = thread::spawn(move || {
+            load_sprites_cpu(
+                &vfs,
+                &asset_folder,
+                registry,
+                atlas_settings,
+                &thread_progress,
+            )
+        });
+        Self { progress, handle }
+    }
+
+    /// From 0 to 1, for drawing a loading bar.
+    pub fn progress(&self) -> f32 {
+        self.progress.fraction()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+
+    /// Waits for the load to finish.
+    pub fn finish(self) -> Result<LoadedSprites, AssetError> {
+        self.handle.join().expect("Sprite loading thread panicked")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use super::*;
+    use crate::{sprite::discover_sprites, vfs::temp_folder};
+
+    /// Every sprite in the folder has a handle, and every frame's pixels made it onto
+    /// its page unchanged, recolours included.
+    fn check_loaded(vfs: &Vfs, loaded: &LoadedSprites) {
+        let names = discover_sprites(vfs, "sprites").unwrap();
+        assert!(!names.is_empty());
+        for name in &names {
+            assert!(
+                loaded.registry.lookup(name).is_some(),
+                "{} not registered",
+                name
+            );
+        }
+        assert_eq!(loaded.registry.len(), names.len());
+        assert_eq!(loaded.sprites.len(), names.len());
+        assert_eq!(loaded.sources.len(), names.len());
+
+        for (sprite, name) in loaded.registry.iter() {
+            let source = &loaded.sources[sprite.index()];
+            let data = &loaded.sprites[sprite.index()];
+            assert_eq!(data.frames.len(), source.data.frames.len(), "{}", name);
+            let images = std::iter::once(&source.image).chain(&source.recolors);
+            let packed = std::iter::once(&data.frames).chain(&data.palette_frames);
+            for (image, frames) in images.zip(packed) {
+                for (from, to) in source.data.frames.iter().zip(frames) {
+                    assert!(!from.rotated);
+                    let page = &loaded.pages[to.page];
+                    for y in 0..from.sample_size.y {
+                        for x in 0..from.sample_size.x {
+                            assert_eq!(
+                                page.get_pixel(to.sample_position.x + x, to.sample_position.y + y),
+                                image.get_pixel(
+                                    from.sample_position.x + x,
+                                    from.sample_position.y + y
+                                ),
+                                "{} pixel {},{}",
+                                name,
+                                x,
+                                y
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn loads_without_a_window() {
+        let vfs = Vfs::Folder("assets".into());
+        let progress = LoadProgress::default();
+        let loaded = load_sprites_cpu(
+            &vfs,
+            "sprites",
+            SpriteRegistry::new(),
+            AtlasSettings::default(),
+            &progress,
+        )
+        .unwrap();
+        check_loaded(&vfs, &loaded);
+        assert_eq!(progress.fraction(), 1.0);
+    }
+
+    #[test]
+    fn loads_in_the_background() {
+        let vfs = Vfs::Folder("assets".into());
+        let loader = SpriteLoader::start(
+            &vfs,
+            "sprites",
+            SpriteRegistry::new(),
+            AtlasSettings::default(),
+        );
+        while !loader.is_finished() {
+            thread::yield_now();
+        }
+        assert_eq!(loader.progress(), 1.0);
+        let loaded = loader.finish().unwrap();
+        check_loaded(&vfs, &loaded);
+    }
+
+    #[test]
+    fn failed_reloads_keep_what_was_loaded() {
+        let folder = temp_folder("loader-reload");
+        let sprites = folder.join("sprites");
+        fs::create_dir_all(&sprites).unwrap();
+        for extension in ["png", "json"] {
+            let file_name = format!("apple.{}", extension);
+            fs::copy(
+                Path::new("assets/sprites").join(&file_name),
+                sprites.join(&file_name),
+            )
+            .unwrap();
+        }
+        let vfs = Vfs::Folder(folder.clone());
+        let settings = AtlasSettings::default();
+        let progress = LoadProgress::default();
+        let loaded =
+            load_sprites_cpu(&vfs, "sprites", SpriteRegistry::new(), settings, &progress).unwrap();
+        let names = ["apple", "pear"].map(|name| name.to_string());
+
+        // a pear with half its files breaks the whole reload
+        fs::copy(sprites.join("apple.json"), sprites.join("pear.json")).unwrap();
+        let reload = |loaded: &LoadedSprites| {
+            reload_sprites_cpu(
+                &vfs,
+                "sprites",
+                &names,
+                &loaded.registry,
+                &loaded.sources,
+                settings,
+            )
+        };
+        assert!(matches!(reload(&loaded), Err(AssetError::MissingFiles(_))));
+        assert!(loaded.registry.lookup("pear").is_none());
+        assert_eq!(loaded.sources.len(), 1);
+
+        // once it is whole it gets a handle, and the apple keeps its sheet without files
+        fs::copy(sprites.join("apple.png"), sprites.join("pear.png")).unwrap();
+        fs::remove_file(sprites.join("apple.png")).unwrap();
+        fs::remove_file(sprites.join("apple.json")).unwrap();
+        let reloaded = reload(&loaded).unwrap();
+        assert_eq!(reloaded.registry.lookup("pear").unwrap().index(), 1);
+        assert_eq!(reloaded.sources[0].name, "apple");
+        assert_eq!(reloaded.sprites.len(), 2);
+        fs::remove_dir_all(&folder).unwrap();
+    }
+}
@@ -1,28 +1,69 @@
-use audio::{load_songs, load_sounds};
+use std::time::Duration;
+
 use raylib::{
-    audio::{Music, RaylibAudio, Sound},
-    color::Color,
-    drawing::RaylibDraw,
-    ffi::KeyboardKey,
+    audio::RaylibAudio, color::Color, drawing::RaylibDraw, ffi::KeyboardKey, RaylibHandle,
+    RaylibThread,
 };
 
-use draw::draw_entities;
-use entity::{Entity, EntityType};
 use glam::Vec2;
-use graphics::Graphics;
 use rand::Rng;
-use settings::SCREEN_DIMS;
-use sprite::{Sprite, SpriteAnimator};
-use state::State;
-
-pub mod audio;
-pub mod draw;
-pub mod entity;
-pub mod graphics;
-pub mod settings;
-pub mod sprite;
-pub mod state;
-pub mod step;
+use spriteloading::{
+    animation::{load_animation_graphs, require_graphs, AnimationGraph},
+    atlas::AtlasSettings,
+    audio,
+    draw::{draw_entities, draw_loading_screen},
+    entity::{Entity, EntityId, EntityType, Role},
+    error::AssetError,
+    game_sprites::GameSprites,
+    graphics::Graphics,
+    loader::SpriteLoader,
+    manifest::MANIFEST_FILE,
+    settings::{
+        ANIMATIONS_FOLDER, ASSET_POLL_INTERVAL_MS, ASSET_RELOAD_DEBOUNCE_MS,
+        COMPILED_ASSETS_FOLDER, SCREEN_DIMS, SPRITES_FOLDER,
+    },
+    sprite::SpriteRegistry,
+    state::State,
+    step,
+    vfs::{join, Vfs},
+    watcher::AssetWatcher,
+};
+
+/// The graphs the game plays by name.
+const REQUIRED_GRAPHS: &[&str] = &["reticle", "man"];
+
+/// Every animation graph, as long as the ones the game plays are there.
+fn load_animations(vfs: &Vfs, registry: &SpriteRegistry) -> Result<Vec<AnimationGraph>, String> {
+    let animations =
+        load_animation_graphs(vfs, ANIMATIONS_FOLDER, registry).map_err(|e| e.to_string())?;
+    require_graphs(&animations, REQUIRED_GRAPHS)?;
+    Ok(animations)
+}
+
+/// Release builds start from the `assets build` output when there is one,
+/// debug builds always read the sprite folder so edits show up straight away.
+fn load_graphics(
+    rl: &mut RaylibHandle,
+    thread: &RaylibThread,
+    vfs: &Vfs,
+) -> Result<Graphics, AssetError> {
+    if !cfg!(debug_assertions) && vfs.exists(&join(COMPILED_ASSETS_FOLDER, MANIFEST_FILE)) {
+        return Graphics::from_compiled(rl, thread, vfs, COMPILED_ASSETS_FOLDER);
+    }
+
+    // decode on other threads and keep the window responsive with a loading bar
+    let loader = SpriteLoader::start(
+        vfs,
+        SPRITES_FOLDER,
+        SpriteRegistry::new(),
+        AtlasSettings::default(),
+    );
+    while !loader.is_finished() {
+        let mut d = rl.begin_drawing(thread);
+        draw_loading_screen(&mut d, loader.progress());
+    }
+    Graphics::from_loaded(rl, thread, loader.finish()?)
+}
 
 fn main() -> Result<(), String> {
     let (mut rl, thread) = raylib::init()
@@ -34,8 +75,43 @@ fn main() -> Result<(), String> {
     rl.set_target_fps(144);
     rl.hide_cursor();
 
-    let sprites_folder = "./assets/sprites";
-    let mut graphics = Graphics::new(&mut rl, &thread, sprites_folder)?;
+    let vfs = match Vfs::locate() {
+        Ok(vfs) => vfs,
+        Err(e) => {
+            println!("Error finding assets: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let mut graphics = match load_graphics(&mut rl, &thread, &vfs) {
+        Ok(graphics) => graphics,
+        Err(e) => {
+            println!("Error loading sprites: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let game_sprites = match GameSprites::lookup(&graphics) {
+        Ok(game_sprites) => game_sprites,
+        Err(e) => {
+            println!("Error loading sprites: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let mut animations = match load_animations(&vfs, &graphics.registry) {
+        Ok(animations) => animations,
+        Err(e) => {
+            println!("Error loading animations: {}", e);
+            std::process::exit(1);
+        }
+    };
+    // only loose files can be edited, there is nothing to watch in a pack
+    let sprite_folders = vfs.disk_paths(SPRITES_FOLDER);
+    let mut watcher = (!sprite_folders.is_empty()).then(|| {
+        AssetWatcher::new(
+            sprite_folders,
+            Duration::from_millis(ASSET_POLL_INTERVAL_MS),
+            Duration::from_millis(ASSET_RELOAD_DEBOUNCE_MS),
+        )
+    });
     let rl_audio_device = match RaylibAudio::init_audio_device() {
         Ok(rl_audio_device) => rl_audio_device,
         Err(e) => {
@@ -43,54 +119,53 @@ fn main() -> Result<(), String> {
             std::process::exit(1);
         }
     };
-    let songs = audio::load_songs(&rl_audio_device);
-    let sounds = audio::load_sounds(&rl_audio_device);
+    let songs = match audio::load_songs(&rl_audio_device, &vfs) {
+        Ok(songs) => songs,
+        Err(e) => {
+            println!("Error loading music: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let sounds = match audio::load_sounds(&rl_audio_device, &vfs) {
+        Ok(sounds) => sounds,
+        Err(e) => {
+            println!("Error loading sounds: {}", e);
+            std::process::exit(1);
+        }
+    };
     let mut audio = audio::Audio::new(songs, sounds);
     let mut state = State::new();
 
     audio.play_song(audio::Song::Playing);
 
     // reticle
-    let reticle_entity = Entity {
-        entity_type: EntityType::Reticle,
-        position: Vec2::new(50.0, 200.0),
-        velocity: Vec2::new(0.0, 0.0),
-        size: Vec2::new(40.0, 40.0),
-        sprite_animator: SpriteAnimator {
-            sprite: Sprite::Reticle,
-            current_frame: 0,
-            current_time: 0.0,
-            scale: 5.0,
-        },
-        follows: None,
-        hp: 10.0,
-        active: true,
-        friction: None,
-        expire_in: None,
+    let mut reticle_entity = Entity {
+        roles: vec![Role::Cursor],
+        ..Entity::new(
+            EntityType::Reticle,
+            Vec2::new(50.0, 200.0),
+            game_sprites.reticle,
+            &graphics.sprites,
+        )
     };
+    reticle_entity
+        .set_animation(&animations, "reticle", &graphics.sprites)
+        .expect("checked at startup");
     state.add_entity(reticle_entity);
 
     // apple
     let rng = &mut rand::thread_rng();
     let apple_id = state.add_entity(Entity {
-        entity_type: EntityType::Apple,
-        position: Vec2::new(
-            rng.gen_range(0.0..SCREEN_DIMS.x as f32),
-            rng.gen_range(0.0..SCREEN_DIMS.y as f32),
-        ),
-        velocity: Vec2::new(0.0, 0.0),
-        size: Vec2::new(48.0, 36.0),
-        sprite_animator: SpriteAnimator {
-            sprite: Sprite::Apple,
-            current_frame: 0,
-            current_time: 0.0,
-            scale: 6.0,
-        },
-        follows: None,
-        hp: 10.0,
-        active: true,
-        friction: None,
-        expire_in: None,
+        roles: vec![Role::Pickup],
+        ..Entity::new(
+            EntityType::Apple,
+            Vec2::new(
+                rng.gen_range(0.0..SCREEN_DIMS.x as f32),
+                rng.gen_range(0.0..SCREEN_DIMS.y as f32),
+            ),
+            game_sprites.apple,
+            &graphics.sprites,
+        )
     });
 
     // trees
@@ -98,55 +173,40 @@ fn main() -> Result<(), String> {
     const NUM_TREES: usize = 20;
     let rng = &mut rand::thread_rng();
     for _ in 0..NUM_TREES {
-        // let scale = rng.gen_range(10.0..15.0);
-        let scale = 10.0;
+        let position = Vec2::new(
+            rng.gen_range(0.0..SCREEN_DIMS.x as f32),
+            rng.gen_range(0.0..SCREEN_DIMS.y as f32),
+        );
         let mut entity = Entity {
-            entity_type: EntityType::Tree,
-            position: Vec2::new(
-                rng.gen_range(0.0..SCREEN_DIMS.x as f32),
-                rng.gen_range(0.0..SCREEN_DIMS.y as f32),
-            ),
-            velocity: Vec2::new(0.0, 0.0),
-            size: Vec2::new(2.0 * scale, 5.0 * scale),
-            sprite_animator: SpriteAnimator {
-                sprite: Sprite::Tree,
-                current_frame: 0,
-                current_time: 0.0,
-                scale,
-            },
-            follows: None,
             hp: 4.0,
-            active: true,
-            friction: None,
-            expire_in: None,
+            ..Entity::new(
+                EntityType::Tree,
+                position,
+                game_sprites.tree,
+                &graphics.sprites,
+            )
         };
         entity.sprite_animator.randomize_frame(&graphics.sprites);
         state.add_entity(entity);
     }
 
     // mans
-    let mut last_man: Option<usize> = None;
+    let mut last_man: Option<EntityId> = None;
     const NUM_MANS: usize = 1;
     for i in 0..NUM_MANS {
-        println!("Adding");
         let follows = if i == 0 { Some(apple_id) } else { last_man };
-        let last_entity_id = state.add_entity(Entity {
-            entity_type: EntityType::Man,
-            position: SCREEN_DIMS.as_vec2() / 2.0,
-            velocity: Vec2::new(0.0, 0.0),
-            size: Vec2::new(16.0, 24.0),
-            sprite_animator: SpriteAnimator {
-                sprite: Sprite::ManIdle,
-                current_frame: 0,
-                current_time: 0.0,
-                scale: 6.0,
-            },
+        let mut man = Entity {
             follows,
-            hp: 10.0,
-            active: true,
-            friction: None,
-            expire_in: None,
-        });
+            ..Entity::new(
+                EntityType::Man,
+                SCREEN_DIMS.as_vec2() / 2.0,
+                game_sprites.man_idle,
+                &graphics.sprites,
+            )
+        };
+        man.set_animation(&animations, "man", &graphics.sprites)
+            .expect("checked at startup");
+        let last_entity_id = state.add_entity(man);
         last_man = Some(last_entity_id);
     }
 
@@ -154,39 +214,79 @@ fn main() -> Result<(), String> {
         audio.update_current_song_stream_data();
 
         if rl.is_key_pressed(KeyboardKey::KEY_R) {
-            match graphics.reload(&mut rl, &thread, sprites_folder) {
-                Ok(_) => println!("Reloaded assets"),
+            match graphics.reload(&mut rl, &thread, &vfs, SPRITES_FOLDER) {
+                Ok(_) => {
+                    step::refresh_sprite_animators(&mut state, &graphics.sprites);
+                    println!("Reloaded assets");
+                    // graphs name sprites, so they can only change along with them
+                    match load_animations(&vfs, &graphics.registry) {
+                        Ok(new_animations) => {
+                            let old_animations = std::mem::replace(&mut animations, new_animations);
+                            step::refresh_animation_states(
+                                &mut state,
+                                &old_animations,
+                                &animations,
+                                &graphics.sprites,
+                            );
+                        }
+                        Err(e) => println!("Failed to reload animations: {}", e),
+                    }
+                }
                 Err(e) => println!("Failed to reload assets: {}", e),
             }
         }
-
-        // arrow keys to move entity 0
-        let vel = 100.0;
-        if rl.is_key_down(KeyboardKey::KEY_RIGHT) {
-            state.entities[0].velocity.x = vel;
-        } else if rl.is_key_down(KeyboardKey::KEY_LEFT) {
-            state.entities[0].velocity.x = -vel;
-        } else {
-            state.entities[0].velocity.x = 0.0;
-        }
-        if rl.is_key_down(KeyboardKey::KEY_DOWN) {
-            state.entities[0].velocity.y = vel;
-        } else if rl.is_key_down(KeyboardKey::KEY_UP) {
-            state.entities[0].velocity.y = -vel;
-        } else {
-            state.entities[0].velocity.y = 0.0;
+        let changed_sprites = watcher.as_mut().map_or(vec![], |watcher| watcher.poll());
+        if !changed_sprites.is_empty() {
+            match graphics.reload_sprites(&mut rl, &thread, &vfs, SPRITES_FOLDER, &changed_sprites)
+            {
+                Ok(_) => {
+                    step::refresh_sprite_animators(&mut state, &graphics.sprites);
+                    println!("Reloaded {}", changed_sprites.join(", "))
+                }
+                Err(e) => println!("Failed to reload {}: {}", changed_sprites.join(", "), e),
+            }
         }
 
-        // set entity 0 position to mouse
-        state.entities[0].position = Vec2::new(rl.get_mouse_x() as f32, rl.get_mouse_y() as f32);
+        for cursor in state.ids_with_role(Role::Cursor) {
+            let Some(reticle) = state.get_mut(cursor) else {
+                continue;
+            };
+            // arrow keys to move the cursors
+            let vel = 100.0;
+            if rl.is_key_down(KeyboardKey::KEY_RIGHT) {
+                reticle.velocity.x = vel;
+            } else if rl.is_key_down(KeyboardKey::KEY_LEFT) {
+                reticle.velocity.x = -vel;
+            } else {
+                reticle.velocity.x = 0.0;
+            }
+            if rl.is_key_down(KeyboardKey::KEY_DOWN) {
+                reticle.velocity.y = vel;
+            } else if rl.is_key_down(KeyboardKey::KEY_UP) {
+                reticle.velocity.y = -vel;
+            } else {
+                reticle.velocity.y = 0.0;
+            }
+
+            // set cursor position to mouse
+            reticle.position = Vec2::new(rl.get_mouse_x() as f32, rl.get_mouse_y() as f32);
+        }
 
         let dt = rl.get_frame_time();
-        step::step(&mut rl, &mut state, &mut audio, &mut graphics, dt);
+        step::step(
+            &mut rl,
+            &mut state,
+            &mut audio,
+            &mut graphics,
+            &game_sprites,
+            &animations,
+            dt,
+        );
 
         let mut d = rl.begin_drawing(&thread);
         d.clear_background(Color::new(134, 163, 118, 255));
 
-        let man_walk = graphics.get_sprite_data(Sprite::ManWalk);
+        let man_walk = graphics.get_sprite_data(game_sprites.man_walk);
         d.draw_text(
             &format!("Man Walk frames: {}", man_walk.frames.len()),
             10,
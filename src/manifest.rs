@@ -0,0 +1,309 @@
+//! The compiled form of the sprite folder, written by the `assets` tool: the packed
+//! atlas pages as pngs plus one json manifest with every sprite's frames, tags and slices.
+//! Loading it skips decoding, parsing and packing every sprite at startup.
+
+use std::{
+    fs::{self, File},
+    io::BufWriter,
+    path::Path,
+};
+
+use glam::UVec2;
+use serde_json::{json, Value};
+
+use crate::{
+    atlas::AtlasSettings,
+    error::AssetError,
+    image::encode_png,
+    loader::{load_sprites_cpu, LoadProgress},
+    sprite::{sprite_data_from_json, sprite_data_to_json, SpriteData, SpriteRegistry},
+    vfs::{join, Vfs},
+};
+
+pub const MANIFEST_FILE: &str = "manifest.json";
+/// bumped whenever the layout changes, so old builds get rejected instead of misread
+pub const MANIFEST_VERSION: u64 = 4;
+
+/// Everything in a compiled asset folder except the page pixels.
+#[derive(Debug)]
+pub struct CompiledAssets {
+    pub registry: SpriteRegistry,
+    /// indexed by sprite handle
+    pub sprites: Vec<SpriteData>,
+    /// page png file names, next to the manifest. Frames say which one they are on
+    pub pages: Vec<String>,
+    pub page_sizes: Vec<UVec2>,
+    pub atlas_settings: AtlasSettings,
+}
+
+/// Loads and packs every sprite in `sprites_folder`, then writes the pages and manifest
+/// to `out_folder` on disk.
+pub fn build_assets(
+    vfs: &Vfs,
+    sprites_folder: &str,
+    out_folder: &Path,
+    atlas_settings: &AtlasSettings,
+) -> Result<CompiledAssets, AssetError> {
+    let loaded = load_sprites_cpu(
+        vfs,
+        sprites_folder,
+        SpriteRegistry::new(),
+        *atlas_settings,
+        &LoadProgress::default(),
+    )?;
+    let page_images = loaded.pages;
+
+    fs::create_dir_all(out_folder).map_err(|e| AssetError::io(out_folder, e))?;
+
+    let mut pages = Vec::with_capacity(page_images.len());
+    let mut pages_json = Vec::with_capacity(page_images.len());
+    for (i, image) in page_images.iter().enumerate() {
+        let file_name = format!("atlas_{}.png", i);
+        encode_png(image, &out_folder.join(&file_name))?;
+        pages_json.push(json!({ "file": file_name, "w": image.width, "h": image.height }));
+        pages.push(file_name);
+    }
+
+    let sprites_json: Vec<Value> = loaded
+        .registry
+        .iter()
+        .zip(loaded.sprites.iter())
+        .map(|((_, name), data)| {
+            let mut sprite_json = sprite_data_to_json(data);
+            sprite_json["name"] = json!(name);
+            sprite_json
+        })
+        .collect();
+    let manifest = json!({
+        "version": MANIFEST_VERSION,
+        "atlas": {
+            "pageSize": atlas_settings.page_size,
+            "padding": atlas_settings.padding,
+            "extrude": atlas_settings.extrude,
+        },
+        "pages": pages_json,
+        "sprites": sprites_json,
+    });
+
+    let manifest_path = out_folder.join(MANIFEST_FILE);
+    let file = File::create(&manifest_path).map_err(|e| AssetError::io(&manifest_path, e))?;
+    serde_json::to_writer(BufWriter::new(file), &manifest).map_err(|source| AssetError::Json {
+        path: manifest_path.clone(),
+        source,
+    })?;
+
+    Ok(CompiledAssets {
+        registry: loaded.registry,
+        sprites: loaded.sprites,
+        pages,
+        page_sizes: page_images.iter().map(|image| image.size()).collect(),
+        atlas_settings: *atlas_settings,
+    })
+}
+
+/// Reads the manifest in `compiled_folder` and checks every frame lands on a real page.
+pub fn load_manifest(vfs: &Vfs, compiled_folder: &str) -> Result<CompiledAssets, AssetError> {
+    let json = vfs.read_json(&join(compiled_folder, MANIFEST_FILE))?;
+    let manifest_path = vfs.full_path(&join(compiled_folder, MANIFEST_FILE));
+    let invalid = |field: &str, cause: String| AssetError::InvalidField {
+        path: manifest_path.clone(),
+        frame: None,
+        field: field.to_string(),
+        cause,
+    };
+    let whole_number = |value: &Value, field: &str| {
+        value
+            .as_u64()
+            .and_then(|value| u32::try_from(value).ok())
+            .ok_or_else(|| invalid(field, "expected a whole number, 0 or more".to_string()))
+    };
+
+    let version = json["version"]
+        .as_u64()
+        .ok_or_else(|| invalid("version", "expected a whole number".to_string()))?;
+    if version != MANIFEST_VERSION {
+        return Err(invalid(
+            "version",
+            format!(
+                "manifest is version {} but this build reads {}, rebuild the assets",
+                version, MANIFEST_VERSION
+            ),
+        ));
+    }
+
+    let atlas = &json["atlas"];
+    let atlas_settings = AtlasSettings {
+        page_size: whole_number(&atlas["pageSize"], "atlas.pageSize")?,
+        padding: whole_number(&atlas["padding"], "atlas.padding")?,
+        extrude: whole_number(&atlas["extrude"], "atlas.extrude")?,
+    };
+
+    let pages_data = json["pages"]
+        .as_array()
+        .ok_or_else(|| invalid("pages", "expected an array".to_string()))?;
+    let mut pages = Vec::with_capacity(pages_data.len());
+    let mut page_sizes = Vec::with_capacity(pages_data.len());
+    for (i, page_data) in pages_data.iter().enumerate() {
+        let field = format!("pages[{}]", i);
+        let file_name = page_data["file"]
+            .as_str()
+            .ok_or_else(|| invalid(&format!("{}.file", field), "expected a string".to_string()))?;
+        pages.push(file_name.to_string());
+        page_sizes.push(UVec2::new(
+            whole_number(&page_data["w"], &format!("{}.w", field))?,
+            whole_number(&page_data["h"], &format!("{}.h", field))?,
+        ));
+    }
+
+    let sprites_data = json["sprites"]
+        .as_array()
+        .ok_or_else(|| invalid("sprites", "expected an array".to_string()))?;
+    let mut registry = SpriteRegistry::new();
+    let mut sprites = Vec::with_capacity(sprites_data.len());
+    for (i, sprite_data) in sprites_data.iter().enumerate() {
+        let field = format!("sprites[{}]", i);
+        let name = sprite_data["name"]
+            .as_str()
+            .ok_or_else(|| invalid(&format!("{}.name", field), "expected a string".to_string()))?;
+        if registry.lookup(name).is_some() {
+            return Err(invalid(
+                &field,
+                format!("sprite {} is in the manifest twice", name),
+            ));
+        }
+        registry.register(name);
+
+        // point field errors at the sprite inside the manifest
+        let data = sprite_data_from_json(sprite_data, &manifest_path).map_err(|e| match e {
+            AssetError::InvalidField {
+                path,
+                frame,
+                field: sprite_field,
+                cause,
+            } => AssetError::InvalidField {
+                path,
+                frame,
+                field: format!("{}.{}", field, sprite_field),
+                cause,
+            },
+            e => e,
+        })?;
+        let recolor_frames = data.palette_frames.iter().flatten();
+        for (j, frame) in data.frames.iter().chain(recolor_frames).enumerate() {
+            let frame_field = format!("{}.frames[{}]", field, j);
+            let Some(page_size) = page_sizes.get(frame.page) else {
+                return Err(invalid(
+                    &frame_field,
+                    format!(
+                        "frame of sprite {} is on page {} but there are {} pages",
+                        name,
+                        frame.page,
+                        page_sizes.len()
+                    ),
+                ));
+            };
+            let end = frame.sample_position + frame.sheet_size();
+            if end.x > page_size.x || end.y > page_size.y {
+                return Err(invalid(
+                    &frame_field,
+                    format!(
+                        "frame of sprite {} reaches past the edge of page {}",
+                        name, frame.page
+                    ),
+                ));
+            }
+        }
+        sprites.push(data);
+    }
+
+    Ok(CompiledAssets {
+        registry,
+        sprites,
+        pages,
+        page_sizes,
+        atlas_settings,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::vfs::temp_folder;
+
+    /// Compiles the game's sprites into a fresh temp folder.
+    fn build(name: &str) -> (PathBuf, CompiledAssets) {
+        let folder = temp_folder(name);
+        let vfs = Vfs::Folder("assets".into());
+        let built = build_assets(&vfs, "sprites", &folder, &AtlasSettings::default()).unwrap();
+        (folder, built)
+    }
+
+    #[test]
+    fn loads_back_what_was_built() {
+        let (folder, built) = build("manifest-round-trip");
+        let loaded = load_manifest(&Vfs::Folder(folder.clone()), "").unwrap();
+
+        assert_eq!(loaded.pages, built.pages);
+        assert_eq!(loaded.page_sizes, built.page_sizes);
+        assert_eq!(
+            loaded.atlas_settings.page_size,
+            built.atlas_settings.page_size
+        );
+        assert!(loaded.registry.iter().eq(built.registry.iter()));
+        for ((_, name), (data, built_data)) in loaded
+            .registry
+            .iter()
+            .zip(loaded.sprites.iter().zip(built.sprites.iter()))
+        {
+            assert_eq!(data.size, built_data.size, "{}", name);
+            assert_eq!(data.tags.len(), built_data.tags.len(), "{}", name);
+            assert_eq!(data.slices.len(), built_data.slices.len(), "{}", name);
+            assert_eq!(data.frames.len(), built_data.frames.len(), "{}", name);
+            for (frame, built_frame) in data.frames.iter().zip(built_data.frames.iter()) {
+                assert_eq!(frame.page, built_frame.page, "{}", name);
+                assert_eq!(
+                    frame.sample_position, built_frame.sample_position,
+                    "{}",
+                    name
+                );
+                assert_eq!(frame.sample_size, built_frame.sample_size, "{}", name);
+                assert_eq!(frame.duration, built_frame.duration, "{}", name);
+            }
+        }
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn rejects_other_versions_and_frames_off_the_pages() {
+        let (folder, _) = build("manifest-rejects");
+        let manifest_path = folder.join(MANIFEST_FILE);
+        let manifest: Value = serde_json::from_slice(&fs::read(&manifest_path).unwrap()).unwrap();
+        let vfs = Vfs::Folder(folder.clone());
+        let rejected_field = |manifest: &Value| {
+            fs::write(&manifest_path, manifest.to_string()).unwrap();
+            match load_manifest(&vfs, "") {
+                Err(AssetError::InvalidField { field, .. }) => field,
+                result => panic!("expected an invalid field, got {:?}", result.map(|_| ())),
+            }
+        };
+
+        let mut old = manifest.clone();
+        old["version"] = json!(MANIFEST_VERSION - 1);
+        assert_eq!(rejected_field(&old), "version");
+
+        let mut past_the_pages = manifest.clone();
+        let num_pages = manifest["pages"].as_array().unwrap().len();
+        past_the_pages["sprites"][0]["frames"][0]["page"] = json!(num_pages);
+        assert_eq!(rejected_field(&past_the_pages), "sprites[0].frames[0]");
+
+        let mut small_page = manifest.clone();
+        let page = manifest["sprites"][0]["frames"][0]["page"]
+            .as_u64()
+            .unwrap() as usize;
+        small_page["pages"][page]["w"] = json!(1);
+        assert_eq!(rejected_field(&small_page), "sprites[0].frames[0]");
+        fs::remove_dir_all(&folder).unwrap();
+    }
+}
@@ -0,0 +1,285 @@
+//! Every asset in one file, so a release is just the executable and `assets.pack`.
+//! Written by `assets pack`, read through `Vfs`.
+//!
+//! Layout, little endian:
+//! - `SPAK` and a u32 version
+//! - a u32 entry count, then for each entry a u32 path length, the path as utf8,
+//!   a u64 offset from the start of the file and a u64 length
+//! - the contents of every file, one after another
+//!
+//! Paths are relative to the asset folder and `/` separated, like `sprites/apple.png`.
+
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::error::AssetError;
+
+const PACK_MAGIC: &[u8; 4] = b"SPAK";
+/// bumped whenever the layout changes, so old packs get rejected instead of misread
+pub const PACK_VERSION: u32 = 1;
+
+#[derive(Copy, Clone, Debug)]
+struct PackEntry {
+    offset: u64,
+    len: u64,
+}
+
+/// The index of a pack file. Each read opens the file again, so one `Pack` can be
+/// read from on several threads at once.
+#[derive(Clone, Debug)]
+pub struct Pack {
+    path: PathBuf,
+    entries: HashMap<String, PackEntry>,
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+impl Pack {
+    /// Reads the index, the file contents are left on disk until asked for.
+    pub fn open(path: &Path) -> Result<Self, AssetError> {
+        let file = File::open(path).map_err(|e| AssetError::io(path, e))?;
+        let file_len = file.metadata().map_err(|e| AssetError::io(path, e))?.len();
+        let mut reader = BufReader::new(file);
+        let truncated = |_| AssetError::decode(path, "pack index is cut short");
+
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic).map_err(truncated)?;
+        if &magic != PACK_MAGIC {
+            return Err(AssetError::decode(path, "not an asset pack"));
+        }
+        let version = read_u32(&mut reader).map_err(truncated)?;
+        if version != PACK_VERSION {
+            return Err(AssetError::decode(
+                path,
+                format!(
+                    "pack is version {} but this build reads {}, repack the assets",
+                    version, PACK_VERSION
+                ),
+            ));
+        }
+
+        let num_entries = read_u32(&mut reader).map_err(truncated)?;
+        // lengths come from the file, nothing gets allocated for one that couldn't fit in it
+        let mut index_pos = 4 + 4 + 4;
+        let mut entries = HashMap::new();
+        for _ in 0..num_entries {
+            let name_len = read_u32(&mut reader).map_err(truncated)? as u64;
+            index_pos += 4;
+            if index_pos + name_len > file_len {
+                return Err(AssetError::decode(path, "pack index is cut short"));
+            }
+            index_pos += name_len + 16;
+            let mut name = vec![0; name_len as usize];
+            reader.read_exact(&mut name).map_err(truncated)?;
+            let name = String::from_utf8(name)
+                .map_err(|_| AssetError::decode(path, "pack has a path that isn't utf8"))?;
+            let entry = PackEntry {
+                offset: read_u64(&mut reader).map_err(truncated)?,
+                len: read_u64(&mut reader).map_err(truncated)?,
+            };
+            if entry
+                .offset
+                .checked_add(entry.len)
+                .is_none_or(|end| end > file_len)
+            {
+                return Err(AssetError::decode(
+                    path,
+                    format!("{} reaches past the end of the pack", name),
+                ));
+            }
+            entries.insert(name, entry);
+        }
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            entries,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.entries.contains_key(name)
+    }
+
+    /// Paths of every file in the pack, in no particular order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.entries.keys().map(|name| name.as_str())
+    }
+
+    pub fn read(&self, name: &str) -> Result<Vec<u8>, AssetError> {
+        let entry_path = self.path.join(name);
+        let Some(entry) = self.entries.get(name) else {
+            return Err(AssetError::MissingFiles(vec![entry_path]));
+        };
+        let mut file = File::open(&self.path).map_err(|e| AssetError::io(&self.path, e))?;
+        file.seek(SeekFrom::Start(entry.offset))
+            .map_err(|e| AssetError::io(&entry_path, e))?;
+        let mut bytes = vec![0; entry.len as usize];
+        file.read_exact(&mut bytes)
+            .map_err(|e| AssetError::io(&entry_path, e))?;
+        Ok(bytes)
+    }
+}
+
+/// Every file under `folder`, as `/` separated paths relative to `root`.
+fn collect_files(root: &Path, folder: &Path, files: &mut Vec<String>) -> Result<(), AssetError> {
+    let entries = fs::read_dir(folder).map_err(|e| AssetError::io(folder, e))?;
+    for entry in entries {
+        let path = entry.map_err(|e| AssetError::io(folder, e))?.path();
+        if path.is_dir() {
+            collect_files(root, &path, files)?;
+            continue;
+        }
+        let Ok(relative) = path.strip_prefix(root) else {
+            continue;
+        };
+        let name: Vec<&str> = relative
+            .components()
+            .filter_map(|component| component.as_os_str().to_str())
+            .collect();
+        files.push(name.join("/"));
+    }
+    Ok(())
+}
+
+/// Packs every file in `asset_folder` into one file at `out_path`, returning the paths
+/// that went in. Other packs in the folder are left out.
+pub fn write_pack(asset_folder: &Path, out_path: &Path) -> Result<Vec<String>, AssetError> {
+    let mut names = vec![];
+    collect_files(asset_folder, asset_folder, &mut names)?;
+    names.retain(|name| !name.ends_with(".pack"));
+    names.sort();
+
+    let mut lens = Vec::with_capacity(names.len());
+    for name in names.iter() {
+        let path = asset_folder.join(name);
+        let meta = fs::metadata(&path).map_err(|e| AssetError::io(&path, e))?;
+        lens.push(meta.len());
+    }
+
+    let index_len: u64 = names.iter().map(|name| 4 + name.len() as u64 + 16).sum();
+    let mut offset = 4 + 4 + 4 + index_len;
+
+    let file = File::create(out_path).map_err(|e| AssetError::io(out_path, e))?;
+    let mut writer = BufWriter::new(file);
+    let write_error = |e| AssetError::io(out_path, e);
+
+    writer.write_all(PACK_MAGIC).map_err(write_error)?;
+    writer
+        .write_all(&PACK_VERSION.to_le_bytes())
+        .map_err(write_error)?;
+    writer
+        .write_all(&(names.len() as u32).to_le_bytes())
+        .map_err(write_error)?;
+    for (name, len) in names.iter().zip(lens.iter()) {
+        writer
+            .write_all(&(name.len() as u32).to_le_bytes())
+            .map_err(write_error)?;
+        writer.write_all(name.as_bytes()).map_err(write_error)?;
+        writer
+            .write_all(&offset.to_le_bytes())
+            .map_err(write_error)?;
+        writer.write_all(&len.to_le_bytes()).map_err(write_error)?;
+        offset += len;
+    }
+
+    for (name, len) in names.iter().zip(lens.iter()) {
+        let path = asset_folder.join(name);
+        let mut file = File::open(&path).map_err(|e| AssetError::io(&path, e))?;
+        // a file that changed size since the index was written would shift everything after it
+        let copied = io::copy(&mut file, &mut writer).map_err(write_error)?;
+        if copied != *len {
+            return Err(AssetError::decode(&path, "changed while being packed"));
+        }
+    }
+    writer.flush().map_err(write_error)?;
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vfs::temp_folder;
+
+    #[test]
+    fn reads_back_what_was_packed() {
+        let folder = temp_folder("pack-round-trip");
+        let assets = folder.join("assets");
+        fs::create_dir_all(assets.join("sprites")).unwrap();
+        let files: [(&str, Vec<u8>); 3] = [
+            ("sprites/apple.png", (0..=255).collect()),
+            ("sprites/apple.json", b"{}".to_vec()),
+            ("empty.txt", vec![]),
+        ];
+        for (name, bytes) in &files {
+            fs::write(assets.join(name), bytes).unwrap();
+        }
+        let pack_path = folder.join("assets.pack");
+
+        let mut names = write_pack(&assets, &pack_path).unwrap();
+        names.sort();
+        assert_eq!(
+            names,
+            vec!["empty.txt", "sprites/apple.json", "sprites/apple.png"]
+        );
+
+        let pack = Pack::open(&pack_path).unwrap();
+        assert_eq!(pack.names().count(), files.len());
+        for (name, bytes) in &files {
+            assert!(pack.contains(name));
+            assert_eq!(&pack.read(name).unwrap(), bytes, "{}", name);
+        }
+        assert!(pack.read("sprites/missing.png").is_err());
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    /// A pack index with one entry, and `data_len` bytes after it.
+    fn pack_bytes(name_len: u32, name: &[u8], offset: u64, len: u64, data_len: usize) -> Vec<u8> {
+        let mut bytes = PACK_MAGIC.to_vec();
+        bytes.extend(PACK_VERSION.to_le_bytes());
+        bytes.extend(1u32.to_le_bytes());
+        bytes.extend(name_len.to_le_bytes());
+        bytes.extend(name);
+        bytes.extend(offset.to_le_bytes());
+        bytes.extend(len.to_le_bytes());
+        bytes.extend(vec![7; data_len]);
+        bytes
+    }
+
+    #[test]
+    fn rejects_lengths_past_the_end() {
+        let folder = temp_folder("pack-bad-lengths");
+        let path = folder.join("bad.pack");
+        let index_len = 4 + 4 + 4 + 4 + 1 + 16;
+
+        fs::write(&path, pack_bytes(1, b"a", index_len, 4, 4)).unwrap();
+        assert_eq!(Pack::open(&path).unwrap().read("a").unwrap(), vec![7; 4]);
+
+        fs::write(&path, pack_bytes(1, b"a", index_len, 5, 4)).unwrap();
+        assert!(Pack::open(&path).is_err());
+        fs::write(&path, pack_bytes(1, b"a", u64::MAX, 4, 4)).unwrap();
+        assert!(Pack::open(&path).is_err());
+        fs::write(&path, pack_bytes(u32::MAX, b"a", index_len, 4, 4)).unwrap();
+        assert!(Pack::open(&path).is_err());
+        fs::write(&path, b"NOPE").unwrap();
+        assert!(Pack::open(&path).is_err());
+        fs::remove_dir_all(&folder).unwrap();
+    }
+}
@@ -0,0 +1,144 @@
+//! Colour variants of a sprite made by swapping one palette for another, so recolours
+//! don't need their own exports.
+//!
+//! Palettes come from the sprite's sidecar file, `<name>.sprite.json`:
+//!
+//! ```json
+//! { "palettes": [
+//!     ["#3f3f74", "#5b6ee1"],
+//!     ["#ac3232", "#d95763"]
+//! ] }
+//! ```
+//!
+//! The first palette lists colours as they appear in the art, each later one lists
+//! what to replace them with, in the same order.
+
+use std::path::Path;
+
+use serde_json::Value;
+
+use crate::{error::AssetError, image::RgbaImage};
+
+/// RGB colours, alpha is left alone when swapping.
+pub type Palette = Vec<[u8; 3]>;
+
+/// Parses `#rrggbb`.
+fn parse_color(color: &str) -> Option<[u8; 3]> {
+    let hex = color.strip_prefix('#')?;
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok();
+    Some([channel(0)?, channel(1)?, channel(2)?])
+}
+
+/// Reads the `palettes` of a sidecar, empty if it has none.
+pub fn load_palettes(json: &Value, path: &Path) -> Result<Vec<Palette>, AssetError> {
+    let invalid = |field: String, cause: &str| AssetError::InvalidField {
+        path: path.to_path_buf(),
+        frame: None,
+        field,
+        cause: cause.to_string(),
+    };
+    let Some(palettes_data) = json.get("palettes") else {
+        return Ok(vec![]);
+    };
+    let palettes_data = palettes_data
+        .as_array()
+        .ok_or_else(|| invalid("palettes".to_string(), "expected an array"))?;
+
+    let mut palettes: Vec<Palette> = Vec::with_capacity(palettes_data.len());
+    for (i, palette_data) in palettes_data.iter().enumerate() {
+        let colors = palette_data
+            .as_array()
+            .ok_or_else(|| invalid(format!("palettes[{}]", i), "expected an array"))?;
+        let mut palette = Vec::with_capacity(colors.len());
+        for (j, color) in colors.iter().enumerate() {
+            let color = color.as_str().and_then(parse_color).ok_or_else(|| {
+                invalid(
+                    format!("palettes[{}][{}]", i, j),
+                    "expected a colour like #rrggbb",
+                )
+            })?;
+            palette.push(color);
+        }
+        if let Some(source) = palettes.first() {
+            if palette.len() != source.len() {
+                return Err(invalid(
+                    format!("palettes[{}]", i),
+                    "has a different number of colours than the first palette",
+                ));
+            }
+        }
+        palettes.push(palette);
+    }
+    Ok(palettes)
+}
+
+/// A copy of `image` with every pixel matching a `source` colour swapped for the
+/// `target` colour at the same index.
+pub fn recolor(image: &RgbaImage, source: &[[u8; 3]], target: &[[u8; 3]]) -> RgbaImage {
+    let mut recolored = image.clone();
+    for pixel in recolored.pixels.chunks_exact_mut(4) {
+        if pixel[3] == 0 {
+            continue;
+        }
+        let found = source.iter().position(|color| color[..] == pixel[..3]);
+        if let Some(i) = found {
+            pixel[..3].copy_from_slice(&target[i]);
+        }
+    }
+    recolored
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn recolors_only_the_palette_colours() {
+        let sidecar = json!({ "palettes": [["#3f3f74", "#5b6ee1"], ["#ac3232", "#D95763"]] });
+        let palettes = load_palettes(&sidecar, Path::new("x.sprite.json")).unwrap();
+        assert_eq!(palettes[1], vec![[0xac, 0x32, 0x32], [0xd9, 0x57, 0x63]]);
+
+        let image = RgbaImage {
+            width: 4,
+            height: 1,
+            pixels: vec![
+                0x3f, 0x3f, 0x74, 255, // first colour
+                0x5b, 0x6e, 0xe1, 128, // second, half see-through
+                0x01, 0x02, 0x03, 255, // not in the palette
+                0x3f, 0x3f, 0x74, 0, // fully transparent
+            ],
+        };
+        let recolored = recolor(&image, &palettes[0], &palettes[1]);
+        assert_eq!(recolored.get_pixel(0, 0), [0xac, 0x32, 0x32, 255]);
+        assert_eq!(recolored.get_pixel(1, 0), [0xd9, 0x57, 0x63, 128]);
+        assert_eq!(recolored.get_pixel(2, 0), [0x01, 0x02, 0x03, 255]);
+        assert_eq!(recolored.get_pixel(3, 0), [0x3f, 0x3f, 0x74, 0]);
+    }
+
+    #[test]
+    fn bad_palettes_are_rejected() {
+        let short = json!({ "palettes": [["#3f3f74", "#5b6ee1"], ["#ac3232"]] });
+        let not_hex = json!({ "palettes": [["#3f3f7g"]] });
+        let no_hash = json!({ "palettes": [["3f3f74"]] });
+        let not_a_list = json!({ "palettes": "#3f3f74" });
+        for (sidecar, field) in [
+            (short, "palettes[1]"),
+            (not_hex, "palettes[0][0]"),
+            (no_hash, "palettes[0][0]"),
+            (not_a_list, "palettes"),
+        ] {
+            match load_palettes(&sidecar, Path::new("x.sprite.json")) {
+                Err(AssetError::InvalidField { field: found, .. }) => assert_eq!(found, field),
+                result => panic!("{} should be rejected, got {:?}", field, result),
+            }
+        }
+        assert!(load_palettes(&json!({}), Path::new("x.sprite.json"))
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -1,3 +1,28 @@
 use glam::UVec2;
 
 pub const SCREEN_DIMS: UVec2 = UVec2::new(800, 800);
+
+pub const ATLAS_PAGE_SIZE: u32 = 2048;
+pub const ATLAS_PADDING: u32 = 1;
+pub const ATLAS_EXTRUDE: u32 = 1;
+
+/// the loose asset folder, see `Vfs::locate` for where it is looked for
+pub const ASSETS_FOLDER: &str = "assets";
+/// everything in the asset folder as one file, written by `cargo run --bin assets -- pack`
+pub const PACK_FILE: &str = "assets.pack";
+/// lists asset roots to stack, like the base assets and then a mod, see `Vfs`
+pub const LAYERS_FILE: &str = "layers.json";
+
+// the rest are inside the asset folder or pack
+pub const SPRITES_FOLDER: &str = "sprites";
+/// where `cargo run --bin assets -- build` writes the packed atlas and manifest
+pub const COMPILED_ASSETS_FOLDER: &str = "build";
+pub const MUSIC_FOLDER: &str = "music";
+pub const SOUNDS_FOLDER: &str = "sounds";
+/// animation state machines, one json file each, see `animation`
+pub const ANIMATIONS_FOLDER: &str = "animations";
+
+/// how often the sprite folder is checked for edited files
+pub const ASSET_POLL_INTERVAL_MS: u64 = 200;
+/// how long a sprite's files have to sit unchanged before it is reloaded
+pub const ASSET_RELOAD_DEBOUNCE_MS: u64 = 300;
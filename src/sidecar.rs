@@ -0,0 +1,230 @@
+//! Per sprite settings the art tools can't store, read from `<name>.sprite.json` next
+//! to the art. Every field is optional:
+//!
+//! ```json
+//! {
+//!     "scale": 6,
+//!     "origin": { "x": 4, "y": 8 },
+//!     "hitbox": { "x": 3, "y": 4, "w": 2, "h": 4 },
+//!     "filter": "point",
+//!     "shadow": true,
+//!     "faces": "right",
+//!     "palettes": [["#639bff"], ["#d95763"]],
+//!     "events": [{ "frame": 1, "name": "footstep" }]
+//! }
+//! ```
+//!
+//! `origin` and `hitbox` are in untrimmed frame pixels and are only used when the art
+//! has no anchor or hitbox slice. `faces` is the side the art looks to, so it can be
+//! mirrored to look the other way when there are no direction tags. Palettes are read
+//! by `palette::load_palettes`. Events are added to any the aseprite file has in its
+//! cel user data.
+
+use std::path::Path;
+
+use glam::{IVec2, Vec2};
+use serde_json::{json, Map, Value};
+
+use crate::{
+    error::AssetError,
+    sprite::{Direction, FrameEvent, PixelRect},
+};
+
+/// How a sprite's pixels are sampled when drawn scaled.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SpriteFilter {
+    /// hard edged pixels
+    #[default]
+    Point,
+    Bilinear,
+}
+
+impl SpriteFilter {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "point" => Some(SpriteFilter::Point),
+            "bilinear" => Some(SpriteFilter::Bilinear),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SpriteFilter::Point => "point",
+            SpriteFilter::Bilinear => "bilinear",
+        }
+    }
+}
+
+/// What entities and animators using a sprite start with.
+#[derive(Clone, Debug)]
+pub struct SpriteDefaults {
+    pub scale: f32,
+    /// the point drawn at the entity position, when the art has no anchor slice
+    pub origin: Option<Vec2>,
+    /// collision bounds, when the art has no hitbox slice
+    pub hitbox: Option<PixelRect>,
+    pub filter: SpriteFilter,
+    pub shadow: bool,
+    /// left or right, `None` never mirrors
+    pub faces: Option<Direction>,
+}
+
+impl Default for SpriteDefaults {
+    fn default() -> Self {
+        Self {
+            scale: 1.0,
+            origin: None,
+            hitbox: None,
+            filter: SpriteFilter::Point,
+            shadow: true,
+            faces: None,
+        }
+    }
+}
+
+/// Reads the defaults out of a sidecar, anything missing keeps its default.
+pub fn load_sprite_defaults(json: &Value, path: &Path) -> Result<SpriteDefaults, AssetError> {
+    let invalid = |field: &str, cause: &str| AssetError::InvalidField {
+        path: path.to_path_buf(),
+        frame: None,
+        field: field.to_string(),
+        cause: cause.to_string(),
+    };
+    let number = |value: &Value, field: &str| {
+        value
+            .as_f64()
+            .map(|value| value as f32)
+            .ok_or_else(|| invalid(field, "expected a number"))
+    };
+    let whole_number = |value: &Value, field: &str| {
+        value
+            .as_i64()
+            .and_then(|value| i32::try_from(value).ok())
+            .ok_or_else(|| invalid(field, "expected a whole number"))
+    };
+    let mut defaults = SpriteDefaults::default();
+
+    if let Some(scale) = json.get("scale") {
+        defaults.scale = number(scale, "scale")?;
+        if defaults.scale <= 0.0 {
+            return Err(invalid("scale", "must be more than 0"));
+        }
+    }
+    if let Some(origin) = json.get("origin") {
+        defaults.origin = Some(Vec2::new(
+            number(&origin["x"], "origin.x")?,
+            number(&origin["y"], "origin.y")?,
+        ));
+    }
+    if let Some(hitbox) = json.get("hitbox") {
+        let size = IVec2::new(
+            whole_number(&hitbox["w"], "hitbox.w")?,
+            whole_number(&hitbox["h"], "hitbox.h")?,
+        );
+        if size.x < 0 || size.y < 0 {
+            return Err(invalid("hitbox", "can't have a negative size"));
+        }
+        defaults.hitbox = Some(PixelRect {
+            position: IVec2::new(
+                whole_number(&hitbox["x"], "hitbox.x")?,
+                whole_number(&hitbox["y"], "hitbox.y")?,
+            ),
+            size: size.as_uvec2(),
+        });
+    }
+    if let Some(filter) = json.get("filter") {
+        defaults.filter = filter
+            .as_str()
+            .and_then(SpriteFilter::from_name)
+            .ok_or_else(|| invalid("filter", "expected \"point\" or \"bilinear\""))?;
+    }
+    if let Some(shadow) = json.get("shadow") {
+        defaults.shadow = shadow
+            .as_bool()
+            .ok_or_else(|| invalid("shadow", "expected true or false"))?;
+    }
+    if let Some(faces) = json.get("faces") {
+        let faces = faces.as_str().and_then(Direction::from_name);
+        if !matches!(faces, Some(Direction::Left | Direction::Right)) {
+            return Err(invalid("faces", "expected \"left\" or \"right\""));
+        }
+        defaults.faces = faces;
+    }
+    Ok(defaults)
+}
+
+/// The inverse of `load_sprite_defaults`, only the fields that aren't the default.
+pub fn sprite_defaults_json(defaults: &SpriteDefaults) -> Value {
+    let mut json = Map::new();
+    if defaults.scale != 1.0 {
+        json.insert("scale".to_string(), json!(defaults.scale));
+    }
+    if let Some(origin) = defaults.origin {
+        json.insert(
+            "origin".to_string(),
+            json!({ "x": origin.x, "y": origin.y }),
+        );
+    }
+    if let Some(hitbox) = defaults.hitbox {
+        json.insert(
+            "hitbox".to_string(),
+            json!({
+                "x": hitbox.position.x,
+                "y": hitbox.position.y,
+                "w": hitbox.size.x,
+                "h": hitbox.size.y,
+            }),
+        );
+    }
+    if defaults.filter != SpriteFilter::default() {
+        json.insert("filter".to_string(), json!(defaults.filter.name()));
+    }
+    if !defaults.shadow {
+        json.insert("shadow".to_string(), json!(false));
+    }
+    if let Some(faces) = defaults.faces {
+        json.insert("faces".to_string(), json!(faces.name()));
+    }
+    Value::Object(json)
+}
+
+/// Reads the `events` list, frames aren't checked against the sprite here.
+pub fn load_frame_events(json: &Value, path: &Path) -> Result<Vec<FrameEvent>, AssetError> {
+    let invalid = |field: String, cause: &str| AssetError::InvalidField {
+        path: path.to_path_buf(),
+        frame: None,
+        field,
+        cause: cause.to_string(),
+    };
+    let Some(events_data) = json.get("events") else {
+        return Ok(vec![]);
+    };
+    let events_data = events_data
+        .as_array()
+        .ok_or_else(|| invalid("events".to_string(), "expected an array"))?;
+
+    let mut events = Vec::with_capacity(events_data.len());
+    for (i, event_data) in events_data.iter().enumerate() {
+        let frame = event_data["frame"]
+            .as_u64()
+            .ok_or_else(|| invalid(format!("events[{}].frame", i), "expected a frame index"))?;
+        let name = event_data["name"]
+            .as_str()
+            .filter(|name| !name.is_empty())
+            .ok_or_else(|| invalid(format!("events[{}].name", i), "expected a name"))?;
+        events.push(FrameEvent {
+            frame: frame as usize,
+            name: name.to_string(),
+        });
+    }
+    events.sort_by_key(|event| event.frame);
+    Ok(events)
+}
+
+pub fn frame_events_json(events: &[FrameEvent]) -> Value {
+    events
+        .iter()
+        .map(|event| json!({ "frame": event.frame, "name": event.name }))
+        .collect()
+}
@@ -1,168 +1,1709 @@
-use std::{fs::File, io::BufReader, path::Path};
+use std::{collections::HashMap, path::Path};
 
-use glam::UVec2;
+use glam::{IVec2, UVec2, Vec2};
 use rand::Rng;
-use serde_json::Value;
-use strum::{EnumCount, EnumIter, IntoEnumIterator};
-
-#[derive(Copy, Clone, Debug, EnumIter, EnumCount, PartialEq, Eq, Hash)]
-pub enum Sprite {
-    ManWalk,
-    ManIdle,
-    Tree,
-    TreeStump,
-    Reticle,
-    Apple,
-    AxeIdle,
-    AxeCutting,
-    Log,
-}
+use serde_json::{json, Value};
+
+use crate::{
+    aseprite::AsepriteFile,
+    error::AssetError,
+    image::{decode_png, RgbaImage},
+    palette::{load_palettes, recolor},
+    sidecar::{
+        frame_events_json, load_frame_events, load_sprite_defaults, sprite_defaults_json,
+        SpriteDefaults,
+    },
+    vfs::{join, Vfs},
+};
+
+/// Cheap handle to a sprite, get one by name from the `SpriteRegistry`.
+/// Handles stay valid across reloads.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Sprite(usize);
 
 impl Sprite {
-    pub fn to_filename(self) -> &'static str {
-        match self {
-            Sprite::ManWalk => "man_walk",
-            Sprite::ManIdle => "man_idle",
-            Sprite::Tree => "tree",
-            Sprite::Reticle => "reticle",
-            Sprite::Apple => "apple",
-            Sprite::AxeIdle => "axe_idle",
-            Sprite::AxeCutting => "axe_cutting",
-            Sprite::TreeStump => "tree_stump",
-            Sprite::Log => "log",
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+/// Maps sprite names, the file names without extension, to handles.
+#[derive(Clone, Debug, Default)]
+pub struct SpriteRegistry {
+    names: Vec<String>,
+    handles: HashMap<String, Sprite>,
+}
+
+impl SpriteRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the existing handle for `name`, or adds it.
+    pub fn register(&mut self, name: &str) -> Sprite {
+        if let Some(sprite) = self.handles.get(name) {
+            return *sprite;
         }
+        let sprite = Sprite(self.names.len());
+        self.names.push(name.to_string());
+        self.handles.insert(name.to_string(), sprite);
+        sprite
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<Sprite> {
+        self.handles.get(name).copied()
+    }
+
+    pub fn name(&self, sprite: Sprite) -> &str {
+        &self.names[sprite.index()]
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// In handle order, which is the order `load_sprites` returns sheets in.
+    pub fn iter(&self) -> impl Iterator<Item = (Sprite, &str)> {
+        self.names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (Sprite(i), name.as_str()))
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Frame {
+    /// top left of the frame's pixels in the sheet
     pub sample_position: UVec2,
+    /// size of the frame's pixels, smaller than `source_size` when trimmed
+    pub sample_size: UVec2,
+    /// where the trimmed pixels sit inside the untrimmed frame
+    pub trim_offset: UVec2,
+    /// size of the frame before trimming
+    pub source_size: UVec2,
+    /// the pixels are stored turned 90 degrees clockwise in the sheet
+    pub rotated: bool,
+    /// which texture the frame is on once packed into the atlas
+    pub page: usize,
     pub duration: f32,
 }
 
-#[derive(Debug)]
+impl Frame {
+    /// Size of the area the frame takes up in the sheet, which is flipped when rotated.
+    pub fn sheet_size(&self) -> UVec2 {
+        if self.rotated {
+            UVec2::new(self.sample_size.y, self.sample_size.x)
+        } else {
+            self.sample_size
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AnimationDirection {
+    Forward,
+    Reverse,
+    PingPong,
+    PingPongReverse,
+}
+
+impl AnimationDirection {
+    pub fn from_aseprite(direction: &str) -> Option<Self> {
+        match direction {
+            "forward" => Some(AnimationDirection::Forward),
+            "reverse" => Some(AnimationDirection::Reverse),
+            "pingpong" => Some(AnimationDirection::PingPong),
+            "pingpong_reverse" => Some(AnimationDirection::PingPongReverse),
+            _ => None,
+        }
+    }
+
+    pub fn to_aseprite(self) -> &'static str {
+        match self {
+            AnimationDirection::Forward => "forward",
+            AnimationDirection::Reverse => "reverse",
+            AnimationDirection::PingPong => "pingpong",
+            AnimationDirection::PingPongReverse => "pingpong_reverse",
+        }
+    }
+
+    /// Whether playback starts at the end of the clip and walks backwards.
+    pub fn starts_reversed(self) -> bool {
+        matches!(
+            self,
+            AnimationDirection::Reverse | AnimationDirection::PingPongReverse
+        )
+    }
+}
+
+/// Which way a sprite faces. A clip can come as a set of tags, one per direction, named
+/// `<clip>_right`, `<clip>_down_left` and so on, or just `right` and so on for the whole
+/// sheet. Four directions or all eight, and sides that aren't drawn are mirrored.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Right,
+    DownRight,
+    Down,
+    DownLeft,
+    Left,
+    UpLeft,
+    Up,
+    UpRight,
+}
+
+impl Direction {
+    pub const ALL: [Direction; 8] = [
+        Direction::Right,
+        Direction::DownRight,
+        Direction::Down,
+        Direction::DownLeft,
+        Direction::Left,
+        Direction::UpLeft,
+        Direction::Up,
+        Direction::UpRight,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Direction::Right => "right",
+            Direction::DownRight => "down_right",
+            Direction::Down => "down",
+            Direction::DownLeft => "down_left",
+            Direction::Left => "left",
+            Direction::UpLeft => "up_left",
+            Direction::Up => "up",
+            Direction::UpRight => "up_right",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|direction| direction.name() == name)
+    }
+
+    /// Unit length, y points down the screen. Straight directions are exactly 0 on the
+    /// other axis, facing code tells sides apart by the sign of x.
+    pub fn vector(self) -> Vec2 {
+        let diagonal = std::f32::consts::FRAC_1_SQRT_2;
+        match self {
+            Direction::Right => Vec2::new(1.0, 0.0),
+            Direction::DownRight => Vec2::new(diagonal, diagonal),
+            Direction::Down => Vec2::new(0.0, 1.0),
+            Direction::DownLeft => Vec2::new(-diagonal, diagonal),
+            Direction::Left => Vec2::new(-1.0, 0.0),
+            Direction::UpLeft => Vec2::new(-diagonal, -diagonal),
+            Direction::Up => Vec2::new(0.0, -1.0),
+            Direction::UpRight => Vec2::new(diagonal, -diagonal),
+        }
+    }
+
+    /// How it looks in a mirror, left and right swap.
+    pub fn mirrored(self) -> Self {
+        match self {
+            Direction::Right => Direction::Left,
+            Direction::DownRight => Direction::DownLeft,
+            Direction::DownLeft => Direction::DownRight,
+            Direction::Left => Direction::Right,
+            Direction::UpLeft => Direction::UpRight,
+            Direction::UpRight => Direction::UpLeft,
+            direction => direction,
+        }
+    }
+
+    /// The tag for `clip` facing this way.
+    pub fn tag_name(self, clip: Option<&str>) -> String {
+        match clip {
+            Some(clip) => format!("{}_{}", clip, self.name()),
+            None => self.name().to_string(),
+        }
+    }
+}
+
+/// A named clip from the aseprite `frameTags`, `from` and `to` are inclusive.
+#[derive(Clone, Debug)]
+pub struct Tag {
+    pub name: String,
+    pub from: usize,
+    pub to: usize,
+    pub direction: AnimationDirection,
+}
+
+/// Slice named this in aseprite gives an entity its collision bounds.
+pub const HITBOX_SLICE: &str = "hitbox";
+/// Slice named this in aseprite gives the point drawn at the entity position,
+/// its pivot if it has one, otherwise the bottom center of the slice.
+pub const ANCHOR_SLICE: &str = "anchor";
+
+/// Rectangle in sprite pixels, relative to the top left of the untrimmed frame.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PixelRect {
+    pub position: IVec2,
+    pub size: UVec2,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct SliceKey {
+    /// the key applies from this frame until the next key
+    pub frame: usize,
+    pub bounds: PixelRect,
+    /// nine-slice center, relative to `bounds`
+    pub center: Option<PixelRect>,
+    /// relative to `bounds`
+    pub pivot: Option<IVec2>,
+}
+
+/// A named region from the aseprite `slices`.
+#[derive(Clone, Debug)]
+pub struct Slice {
+    pub name: String,
+    /// sorted by frame
+    pub keys: Vec<SliceKey>,
+    /// aseprite user data text
+    pub data: Option<String>,
+}
+
+/// Something that happens when an animation reaches a frame, like a footstep landing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FrameEvent {
+    pub frame: usize,
+    pub name: String,
+}
+
+impl Slice {
+    /// The key in effect on `frame`, if the slice exists yet.
+    pub fn key_at(&self, frame: usize) -> Option<&SliceKey> {
+        self.keys.iter().rev().find(|key| key.frame <= frame)
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct SpriteData {
     pub frames: Vec<Frame>,
     pub size: UVec2,
+    pub tags: Vec<Tag>,
+    pub slices: Vec<Slice>,
+    /// frames of the palette swapped copies once packed, `palette_frames[0]` is palette 1,
+    /// laid out like `frames`
+    pub palette_frames: Vec<Vec<Frame>>,
+    /// from the sidecar, if the sprite has one
+    pub defaults: SpriteDefaults,
+    /// from aseprite cel user data and the sidecar, sorted by frame
+    pub events: Vec<FrameEvent>,
 }
 
-pub fn load_sprites(asset_folder: &str) -> Result<Vec<SpriteData>, String> {
+impl SpriteData {
+    /// Palette 0 is the art as drawn, there is always at least that one.
+    pub fn palette_count(&self) -> usize {
+        self.palette_frames.len() + 1
+    }
+
+    /// The frames recoloured with `palette`, palettes the sprite doesn't have use the original.
+    pub fn frames_for_palette(&self, palette: usize) -> &[Frame] {
+        match palette.checked_sub(1) {
+            Some(i) if i < self.palette_frames.len() => &self.palette_frames[i],
+            _ => &self.frames,
+        }
+    }
+
+    pub fn get_tag(&self, name: &str) -> Option<&Tag> {
+        self.tags.iter().find(|tag| tag.name == name)
+    }
+
+    /// Names of the events on `frame`.
+    pub fn events_on(&self, frame: usize) -> impl Iterator<Item = &str> {
+        self.events
+            .iter()
+            .filter(move |event| event.frame == frame)
+            .map(|event| event.name.as_str())
+    }
+
+    pub fn get_slice(&self, name: &str) -> Option<&Slice> {
+        self.slices.iter().find(|slice| slice.name == name)
+    }
+
+    /// Bounds of the named slice on `frame`.
+    pub fn slice_bounds(&self, name: &str, frame: usize) -> Option<PixelRect> {
+        let key = self.get_slice(name)?.key_at(frame)?;
+        Some(key.bounds)
+    }
+
+    /// Collision bounds on `frame` from the hitbox slice, or the sidecar's hitbox.
+    pub fn hitbox(&self, frame: usize) -> Option<PixelRect> {
+        self.slice_bounds(HITBOX_SLICE, frame)
+            .or(self.defaults.hitbox)
+    }
+
+    /// The point of `frame` that sits on the entity position, in untrimmed frame pixels.
+    /// From the anchor slice, then the sidecar's origin, and otherwise the bottom center,
+    /// our feet style origin.
+    pub fn anchor(&self, frame: usize) -> Vec2 {
+        let anchor = self
+            .get_slice(ANCHOR_SLICE)
+            .and_then(|slice| slice.key_at(frame));
+        match anchor {
+            Some(SliceKey {
+                bounds,
+                pivot: Some(pivot),
+                ..
+            }) => (bounds.position + *pivot).as_vec2(),
+            Some(SliceKey { bounds, .. }) => {
+                bounds.position.as_vec2() + bounds.size.as_vec2() * Vec2::new(0.5, 1.0)
+            }
+            None => self.defaults.origin.unwrap_or_else(|| {
+                let source_size = self.frames[frame].source_size.as_vec2();
+                source_size * Vec2::new(0.5, 1.0)
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+impl SpriteData {
+    /// `count` untrimmed frames of `size` in a row, 100ms each, and nothing else.
+    pub(crate) fn strip(count: u32, size: UVec2) -> Self {
+        let frames = (0..count)
+            .map(|i| Frame {
+                sample_position: UVec2::new(i * size.x, 0),
+                sample_size: size,
+                trim_offset: UVec2::ZERO,
+                source_size: size,
+                rotated: false,
+                page: 0,
+                duration: 100.0,
+            })
+            .collect();
+        SpriteData {
+            frames,
+            size,
+            tags: vec![],
+            slices: vec![],
+            palette_frames: vec![],
+            defaults: SpriteDefaults::default(),
+            events: vec![],
+        }
+    }
+}
+
+/// A loaded sprite and its pixels, before they get packed into the atlas.
+#[derive(Clone, Debug)]
+pub struct SpriteSheet {
+    pub name: String,
+    pub data: SpriteData,
+    pub image: RgbaImage,
+    /// `image` recoloured with each palette after the first from the sidecar
+    pub recolors: Vec<RgbaImage>,
+}
+
+/// Optional file next to a sprite's art with settings the art tools can't store,
+/// `<name>.sprite.json`.
+pub const SIDECAR_SUFFIX: &str = ".sprite.json";
+
+/// The sprite a file in the sprite folder belongs to, if it is one of its files.
+pub fn sprite_name_of(path: &Path) -> Option<&str> {
+    let file_name = path.file_name()?.to_str()?;
+    if let Some(name) = file_name.strip_suffix(SIDECAR_SUFFIX) {
+        return Some(name);
+    }
+    let (name, extension) = file_name.rsplit_once('.')?;
+    matches!(extension, "png" | "json" | "aseprite").then_some(name)
+}
+
+/// Names of everything in the folder that looks like part of a sprite, sorted.
+/// A sidecar on its own doesn't make a sprite.
+pub fn discover_sprites(vfs: &Vfs, asset_folder: &str) -> Result<Vec<String>, AssetError> {
+    let mut names = vec![];
+    for file_name in vfs.list(asset_folder)? {
+        if file_name.ends_with(SIDECAR_SUFFIX) {
+            continue;
+        }
+        if let Some(name) = sprite_name_of(Path::new(&file_name)) {
+            names.push(name.to_string());
+        }
+    }
+    names.sort();
+    names.dedup();
+    Ok(names)
+}
+
+/// Loads every sprite in the folder, registering any new ones.
+/// The returned sheets are indexed by sprite handle.
+pub fn load_sprites(
+    vfs: &Vfs,
+    asset_folder: &str,
+    registry: &mut SpriteRegistry,
+) -> Result<Vec<SpriteSheet>, AssetError> {
+    register_sprites(vfs, asset_folder, registry)?;
+
+    let mut sprites: Vec<SpriteSheet> = vec![];
+    for (_, filename) in registry.iter() {
+        sprites.push(load_sprite(vfs, asset_folder, filename)?);
+    }
+    Ok(sprites)
+}
+
+/// Registers every sprite in the folder and checks each registered sprite has its files.
+pub fn register_sprites(
+    vfs: &Vfs,
+    asset_folder: &str,
+    registry: &mut SpriteRegistry,
+) -> Result<(), AssetError> {
+    for name in discover_sprites(vfs, asset_folder)? {
+        registry.register(&name);
+    }
+
     // before we load them lets just check all the files are there
+    // an exported png + json pair is used if present, otherwise the .aseprite source
+    // sprites registered by an earlier load count too, handles to them are still around
 
     let mut missing_files = vec![];
-    for sprite in Sprite::iter() {
-        let filename = sprite.to_filename();
-        let png_path = Path::new(asset_folder).join(format!("{}.png", filename));
-        let json_path = Path::new(asset_folder).join(format!("{}.json", filename));
-        if !png_path.exists() {
-            missing_files.push(png_path.to_str().unwrap().to_string());
+    for (_, filename) in registry.iter() {
+        let png_path = join(asset_folder, &format!("{}.png", filename));
+        let json_path = join(asset_folder, &format!("{}.json", filename));
+        let aseprite_path = join(asset_folder, &format!("{}.aseprite", filename));
+        let art = vfs.layer_with(&[&png_path, &json_path, &aseprite_path]);
+        if (art.exists(&png_path) && art.exists(&json_path)) || art.exists(&aseprite_path) {
+            continue;
+        }
+        if !art.exists(&png_path) {
+            missing_files.push(art.full_path(&png_path));
         }
-        if !json_path.exists() {
-            missing_files.push(json_path.to_str().unwrap().to_string());
+        if !art.exists(&json_path) {
+            missing_files.push(art.full_path(&json_path));
         }
     }
-
     if !missing_files.is_empty() {
-        // build up a big error string with newlines and tabs
-        // make it print in red
-        println!("\x1b[31m");
-        println!("Missing files:");
-        for file in missing_files.iter() {
-            println!("\t{}", file);
+        return Err(AssetError::MissingFiles(missing_files));
+    }
+    Ok(())
+}
+
+/// Loads one sprite, from its png + json export if both are there, otherwise its .aseprite.
+pub fn load_sprite(vfs: &Vfs, asset_folder: &str, name: &str) -> Result<SpriteSheet, AssetError> {
+    let png_path = join(asset_folder, &format!("{}.png", name));
+    let json_path = join(asset_folder, &format!("{}.json", name));
+    let aseprite_path = join(asset_folder, &format!("{}.aseprite", name));
+    // a layer that overrides a sprite replaces all of its art, not just the files it has
+    let art = vfs.layer_with(&[&png_path, &json_path, &aseprite_path]);
+    let (data, image) = if art.exists(&png_path) && art.exists(&json_path) {
+        let png_bytes = art.read(&png_path)?;
+        (
+            load_sprite_data(art, &json_path)?,
+            decode_png(&png_bytes, &art.full_path(&png_path))?,
+        )
+    } else {
+        if !art.exists(&aseprite_path) {
+            return Err(AssetError::MissingFiles(vec![
+                art.full_path(&png_path),
+                art.full_path(&json_path),
+            ]));
+        }
+        let file = AsepriteFile::load(art, &aseprite_path)?;
+        (file.sprite_data(), file.render_sheet())
+    };
+
+    let sidecar_path = join(asset_folder, &format!("{}{}", name, SIDECAR_SUFFIX));
+    let mut data = data;
+    let recolors = if vfs.exists(&sidecar_path) {
+        let sidecar = vfs.read_json(&sidecar_path)?;
+        data.defaults = load_sprite_defaults(&sidecar, &vfs.full_path(&sidecar_path))?;
+        let events = load_frame_events(&sidecar, &vfs.full_path(&sidecar_path))?;
+        if let Some(event) = events.iter().find(|event| event.frame >= data.frames.len()) {
+            return Err(AssetError::InvalidField {
+                path: vfs.full_path(&sidecar_path),
+                frame: None,
+                field: "events".to_string(),
+                cause: format!(
+                    "{} is on frame {} but the sprite has {} frames",
+                    event.name,
+                    event.frame,
+                    data.frames.len()
+                ),
+            });
         }
-        println!("\x1b[0m");
-        return Err(format!("Missing files: {:?}", missing_files));
+        data.events.extend(events);
+        data.events.sort_by_key(|event| event.frame);
+        let palettes = load_palettes(&sidecar, &vfs.full_path(&sidecar_path))?;
+        palettes
+            .iter()
+            .skip(1)
+            .map(|palette| recolor(&image, &palettes[0], palette))
+            .collect()
+    } else {
+        vec![]
+    };
+
+    Ok(SpriteSheet {
+        name: name.to_string(),
+        data,
+        image,
+        recolors,
+    })
+}
+
+pub fn load_sprite_data(vfs: &Vfs, json_path: &str) -> Result<SpriteData, AssetError> {
+    let json = vfs.read_json(json_path)?;
+    sprite_data_from_json(&json, &vfs.full_path(json_path))
+}
+
+/// Which file, and which frame in it, a json value came from.
+#[derive(Copy, Clone)]
+struct JsonContext<'a> {
+    path: &'a Path,
+    frame: Option<&'a str>,
+}
+
+impl<'a> JsonContext<'a> {
+    fn new(path: &'a Path) -> Self {
+        Self { path, frame: None }
     }
 
-    let mut sprites: Vec<SpriteData> = vec![];
-    for sprite in Sprite::iter() {
-        let filename = sprite.to_filename();
-        let json_path = Path::new(asset_folder).join(format!("{}.json", filename));
-        let sprite_data = load_sprite_data(&json_path)?;
-        sprites.push(sprite_data);
+    fn in_frame(self, frame: &'a str) -> Self {
+        Self {
+            frame: Some(frame),
+            ..self
+        }
+    }
+
+    fn error(&self, field: &str, cause: impl Into<String>) -> AssetError {
+        AssetError::InvalidField {
+            path: self.path.to_path_buf(),
+            frame: self.frame.map(|frame| frame.to_string()),
+            field: field.to_string(),
+            cause: cause.into(),
+        }
+    }
+
+    fn u32(&self, value: &Value, field: &str) -> Result<u32, AssetError> {
+        value
+            .as_u64()
+            .and_then(|value| u32::try_from(value).ok())
+            .ok_or_else(|| self.error(field, "expected a whole number, 0 or more"))
+    }
+
+    fn i32(&self, value: &Value, field: &str) -> Result<i32, AssetError> {
+        value
+            .as_i64()
+            .and_then(|value| i32::try_from(value).ok())
+            .ok_or_else(|| self.error(field, "expected a whole number"))
+    }
+
+    fn f32(&self, value: &Value, field: &str) -> Result<f32, AssetError> {
+        value
+            .as_f64()
+            .map(|value| value as f32)
+            .ok_or_else(|| self.error(field, "expected a number"))
+    }
+
+    fn str<'v>(&self, value: &'v Value, field: &str) -> Result<&'v str, AssetError> {
+        value
+            .as_str()
+            .ok_or_else(|| self.error(field, "expected a string"))
+    }
+
+    fn array<'v>(&self, value: &'v Value, field: &str) -> Result<&'v Vec<Value>, AssetError> {
+        value
+            .as_array()
+            .ok_or_else(|| self.error(field, "expected an array"))
     }
-    Ok(sprites)
 }
 
-pub fn load_sprite_data(json_path: &Path) -> Result<SpriteData, String> {
-    let file = File::open(json_path).map_err(|e| format!("Failed to open JSON file: {}", e))?;
-    let reader = BufReader::new(file);
-    let json: Value =
-        serde_json::from_reader(reader).map_err(|e| format!("Failed to parse JSON: {}", e))?;
+/// Reads the aseprite/TexturePacker style json layout, also used per sprite in the
+/// compiled manifest. `path` is only used to say where errors are.
+pub fn sprite_data_from_json(json: &Value, path: &Path) -> Result<SpriteData, AssetError> {
+    let ctx = JsonContext::new(path);
+    let (frames, size) = load_frames(&json["frames"], ctx)?;
+    if frames.is_empty() {
+        return Err(ctx.error("frames", "sprite has no frames"));
+    }
+    let tags = load_frame_tags(json, frames.len(), ctx)?;
+    let slices = load_slices(json, ctx)?;
 
-    let frames = json["frames"].as_object().ok_or("Invalid JSON structure")?;
+    // only the compiled manifest has these, exports get recoloured at load time
+    let mut palette_frames = vec![];
+    if let Some(palette_frames_data) = json.get("paletteFrames") {
+        for palette_frames_data in ctx.array(palette_frames_data, "paletteFrames")? {
+            let (recolor_frames, _) = load_frames(palette_frames_data, ctx)?;
+            if recolor_frames.len() != frames.len() {
+                return Err(ctx.error(
+                    "paletteFrames",
+                    "each palette needs as many frames as the sprite",
+                ));
+            }
+            palette_frames.push(recolor_frames);
+        }
+    }
+    // and these, exports get them from the sidecar
+    let defaults = match json.get("defaults") {
+        Some(defaults_data) => load_sprite_defaults(defaults_data, path).map_err(|e| match e {
+            AssetError::InvalidField {
+                path,
+                frame,
+                field,
+                cause,
+            } => AssetError::InvalidField {
+                path,
+                frame,
+                field: format!("defaults.{}", field),
+                cause,
+            },
+            e => e,
+        })?,
+        None => SpriteDefaults::default(),
+    };
+    let events = load_frame_events(json, path)?;
+    if events.iter().any(|event| event.frame >= frames.len()) {
+        return Err(ctx.error("events", "event on a frame the sprite doesn't have"));
+    }
+
+    Ok(SpriteData {
+        frames,
+        size,
+        tags,
+        slices,
+        palette_frames,
+        defaults,
+        events,
+    })
+}
+
+/// The frames and the size of the first one that isn't empty.
+fn load_frames(frames_data: &Value, ctx: JsonContext) -> Result<(Vec<Frame>, UVec2), AssetError> {
+    let frames = ordered_frames(frames_data, ctx)?;
     let mut sprite_frames = Vec::new();
     let mut size = UVec2::ZERO;
 
-    for (_, frame_data) in frames {
-        let frame = frame_data["frame"]
-            .as_object()
-            .ok_or("Invalid frame data")?;
-        let x = frame["x"].as_u64().ok_or("Invalid x")? as u32;
-        let y = frame["y"].as_u64().ok_or("Invalid y")? as u32;
-        let duration = frame_data["duration"].as_f64().ok_or("Invalid duration")? as f32;
+    for (key, frame_data) in frames.iter() {
+        let ctx = ctx.in_frame(key);
+        let frame = &frame_data["frame"];
+        if !frame.is_object() {
+            return Err(ctx.error("frame", "expected an object"));
+        }
+        let x = ctx.u32(&frame["x"], "frame.x")?;
+        let y = ctx.u32(&frame["y"], "frame.y")?;
+        let w = ctx.u32(&frame["w"], "frame.w")?;
+        let h = ctx.u32(&frame["h"], "frame.h")?;
+        let duration = ctx.f32(&frame_data["duration"], "duration")?;
+        let rotated = frame_data["rotated"].as_bool().unwrap_or(false);
+        // only the compiled manifest spreads frames over several pages
+        let page = frame_data["page"].as_u64().unwrap_or(0) as usize;
+
+        // untrimmed exports may leave these out, then the frame is the whole source
+        let trimmed = frame_data["trimmed"].as_bool().unwrap_or(false);
+        let trim_offset = if trimmed {
+            let sprite_source = &frame_data["spriteSourceSize"];
+            UVec2::new(
+                ctx.u32(&sprite_source["x"], "spriteSourceSize.x")?,
+                ctx.u32(&sprite_source["y"], "spriteSourceSize.y")?,
+            )
+        } else {
+            UVec2::ZERO
+        };
+        let source_size = match frame_data.get("sourceSize") {
+            Some(source_size) => UVec2::new(
+                ctx.u32(&source_size["w"], "sourceSize.w")?,
+                ctx.u32(&source_size["h"], "sourceSize.h")?,
+            ),
+            None => UVec2::new(w, h),
+        };
 
         sprite_frames.push(Frame {
             sample_position: UVec2::new(x, y),
+            sample_size: UVec2::new(w, h),
+            trim_offset,
+            source_size,
+            rotated,
+            page,
             duration,
         });
 
         if size == UVec2::ZERO {
-            let w = frame["w"].as_u64().ok_or("Invalid w")? as u32;
-            let h = frame["h"].as_u64().ok_or("Invalid h")? as u32;
-            size = UVec2::new(w, h);
+            size = source_size;
         }
     }
+    Ok((sprite_frames, size))
+}
 
-    Ok(SpriteData {
-        frames: sprite_frames,
-        size,
+/// Frames in playback order with their keys, for both the aseprite "Hash" and "Array"
+/// json layouts. Array frames are keyed by their index.
+fn ordered_frames<'v>(
+    frames: &'v Value,
+    ctx: JsonContext,
+) -> Result<Vec<(String, &'v Value)>, AssetError> {
+    match frames {
+        Value::Array(frames) => Ok(frames
+            .iter()
+            .enumerate()
+            .map(|(i, frame)| (i.to_string(), frame))
+            .collect()),
+        Value::Object(frames) => {
+            let mut frames: Vec<(&String, &Value)> = frames.iter().collect();
+            // keys look like "man_walk 10.aseprite", sort on the number so 10 comes after 9
+            // otherwise fall back to the order they were written in
+            if frames.iter().all(|(key, _)| frame_number(key).is_some()) {
+                frames.sort_by_key(|(key, _)| frame_number(key));
+            }
+            Ok(frames
+                .into_iter()
+                .map(|(key, frame)| (key.clone(), frame))
+                .collect())
+        }
+        _ => Err(ctx.error("frames", "expected an array or an object")),
+    }
+}
+
+/// The trailing frame number of a hash export key, ignoring the file extension.
+fn frame_number(key: &str) -> Option<usize> {
+    let stem = key.rsplit_once('.').map_or(key, |(stem, _)| stem);
+    let digits_start = stem
+        .rfind(|c: char| !c.is_ascii_digit())
+        .map_or(0, |i| i + 1);
+    stem[digits_start..].parse().ok()
+}
+
+fn load_pixel_rect(rect: &Value, field: &str, ctx: JsonContext) -> Result<PixelRect, AssetError> {
+    Ok(PixelRect {
+        position: IVec2::new(
+            ctx.i32(&rect["x"], &format!("{}.x", field))?,
+            ctx.i32(&rect["y"], &format!("{}.y", field))?,
+        ),
+        size: UVec2::new(
+            ctx.u32(&rect["w"], &format!("{}.w", field))?,
+            ctx.u32(&rect["h"], &format!("{}.h", field))?,
+        ),
     })
 }
 
+fn load_slices(json: &Value, ctx: JsonContext) -> Result<Vec<Slice>, AssetError> {
+    let Some(slices_data) = json["meta"]["slices"].as_array() else {
+        return Ok(vec![]);
+    };
+
+    let mut slices = Vec::with_capacity(slices_data.len());
+    for (i, slice_data) in slices_data.iter().enumerate() {
+        let field = format!("meta.slices[{}]", i);
+        let name = ctx.str(&slice_data["name"], &format!("{}.name", field))?;
+        let keys_data = ctx.array(&slice_data["keys"], &format!("{}.keys", field))?;
+        let mut keys = Vec::with_capacity(keys_data.len());
+        for (j, key_data) in keys_data.iter().enumerate() {
+            let field = format!("{}.keys[{}]", field, j);
+            let frame = ctx.u32(&key_data["frame"], &format!("{}.frame", field))? as usize;
+            let bounds = load_pixel_rect(&key_data["bounds"], &format!("{}.bounds", field), ctx)?;
+            let center = match key_data.get("center") {
+                Some(center) => Some(load_pixel_rect(center, &format!("{}.center", field), ctx)?),
+                None => None,
+            };
+            let pivot = match key_data.get("pivot") {
+                Some(pivot) => Some(IVec2::new(
+                    ctx.i32(&pivot["x"], &format!("{}.pivot.x", field))?,
+                    ctx.i32(&pivot["y"], &format!("{}.pivot.y", field))?,
+                )),
+                None => None,
+            };
+            keys.push(SliceKey {
+                frame,
+                bounds,
+                center,
+                pivot,
+            });
+        }
+        keys.sort_by_key(|key| key.frame);
+
+        slices.push(Slice {
+            name: name.to_string(),
+            keys,
+            data: slice_data["data"].as_str().map(|data| data.to_string()),
+        });
+    }
+    Ok(slices)
+}
+
+fn load_frame_tags(
+    json: &Value,
+    num_frames: usize,
+    ctx: JsonContext,
+) -> Result<Vec<Tag>, AssetError> {
+    // older exports and sheets without tags just leave this out
+    let Some(frame_tags) = json["meta"]["frameTags"].as_array() else {
+        return Ok(vec![]);
+    };
+
+    let mut tags = Vec::with_capacity(frame_tags.len());
+    for (i, tag_data) in frame_tags.iter().enumerate() {
+        let field = format!("meta.frameTags[{}]", i);
+        let name = ctx.str(&tag_data["name"], &format!("{}.name", field))?;
+        let from = ctx.u32(&tag_data["from"], &format!("{}.from", field))? as usize;
+        let to = ctx.u32(&tag_data["to"], &format!("{}.to", field))? as usize;
+        if from > to || to >= num_frames {
+            return Err(ctx.error(
+                &field,
+                format!(
+                    "tag {} has frame range {}..={} but there are {} frames",
+                    name, from, to, num_frames
+                ),
+            ));
+        }
+        let direction = match tag_data.get("direction") {
+            Some(direction) => {
+                let field = format!("{}.direction", field);
+                let direction = ctx.str(direction, &field)?;
+                AnimationDirection::from_aseprite(direction)
+                    .ok_or_else(|| ctx.error(&field, format!("unknown direction {}", direction)))?
+            }
+            None => AnimationDirection::Forward,
+        };
+
+        tags.push(Tag {
+            name: name.to_string(),
+            from,
+            to,
+            direction,
+        });
+    }
+    Ok(tags)
+}
+
+fn pixel_rect_json(rect: &PixelRect) -> Value {
+    json!({
+        "x": rect.position.x,
+        "y": rect.position.y,
+        "w": rect.size.x,
+        "h": rect.size.y,
+    })
+}
+
+fn frame_json(frame: &Frame) -> Value {
+    json!({
+        "frame": {
+            "x": frame.sample_position.x,
+            "y": frame.sample_position.y,
+            "w": frame.sample_size.x,
+            "h": frame.sample_size.y,
+        },
+        "rotated": frame.rotated,
+        "trimmed": true,
+        "spriteSourceSize": {
+            "x": frame.trim_offset.x,
+            "y": frame.trim_offset.y,
+            "w": frame.sample_size.x,
+            "h": frame.sample_size.y,
+        },
+        "sourceSize": { "w": frame.source_size.x, "h": frame.source_size.y },
+        "duration": frame.duration,
+        "page": frame.page,
+    })
+}
+
+/// The inverse of `sprite_data_from_json`, frames are written as an array with their page.
+pub fn sprite_data_to_json(data: &SpriteData) -> Value {
+    let frames: Vec<Value> = data.frames.iter().map(frame_json).collect();
+    let palette_frames: Vec<Vec<Value>> = data
+        .palette_frames
+        .iter()
+        .map(|frames| frames.iter().map(frame_json).collect())
+        .collect();
+
+    let tags: Vec<Value> = data
+        .tags
+        .iter()
+        .map(|tag| {
+            json!({
+                "name": tag.name,
+                "from": tag.from,
+                "to": tag.to,
+                "direction": tag.direction.to_aseprite(),
+            })
+        })
+        .collect();
+
+    let slices: Vec<Value> = data
+        .slices
+        .iter()
+        .map(|slice| {
+            let keys: Vec<Value> = slice
+                .keys
+                .iter()
+                .map(|key| {
+                    let mut key_json = json!({
+                        "frame": key.frame,
+                        "bounds": pixel_rect_json(&key.bounds),
+                    });
+                    if let Some(center) = &key.center {
+                        key_json["center"] = pixel_rect_json(center);
+                    }
+                    if let Some(pivot) = key.pivot {
+                        key_json["pivot"] = json!({ "x": pivot.x, "y": pivot.y });
+                    }
+                    key_json
+                })
+                .collect();
+            let mut slice_json = json!({ "name": slice.name, "keys": keys });
+            if let Some(data) = &slice.data {
+                slice_json["data"] = json!(data);
+            }
+            slice_json
+        })
+        .collect();
+
+    let mut sprite_json = json!({
+        "frames": frames,
+        "meta": {
+            "frameTags": tags,
+            "slices": slices,
+        },
+    });
+    if !palette_frames.is_empty() {
+        sprite_json["paletteFrames"] = json!(palette_frames);
+    }
+    let defaults = sprite_defaults_json(&data.defaults);
+    if defaults
+        .as_object()
+        .is_some_and(|defaults| !defaults.is_empty())
+    {
+        sprite_json["defaults"] = defaults;
+    }
+    if !data.events.is_empty() {
+        sprite_json["events"] = frame_events_json(&data.events);
+    }
+    sprite_json
+}
+
+/// Whether the sprite has a tag called `clip`, or a set of direction tags for it.
+fn has_clip(sprite_data: &SpriteData, clip: &str) -> bool {
+    sprite_data.get_tag(clip).is_some()
+        || Direction::ALL.iter().any(|direction| {
+            sprite_data
+                .get_tag(&direction.tag_name(Some(clip)))
+                .is_some()
+        })
+}
+
+/// How an animator walks through the frames it is playing.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum PlayMode {
+    /// in the tag's own direction, over and over
+    #[default]
+    Loop,
+    /// in the tag's own direction once, then stop on the last frame and finish
+    Once,
+    /// there and back, whatever the tag says
+    PingPong,
+    /// backwards, whatever the tag says
+    Reverse,
+}
+
+impl PlayMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "loop" => Some(PlayMode::Loop),
+            "once" => Some(PlayMode::Once),
+            "pingpong" => Some(PlayMode::PingPong),
+            "reverse" => Some(PlayMode::Reverse),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct SpriteAnimator {
     pub sprite: Sprite,
     pub current_frame: usize,
+    /// ms spent on the current frame
     pub current_time: f32,
     pub scale: f32,
+    /// name of the tag being played, `None` plays every frame in the sheet
+    pub clip: Option<String>,
+    /// the clip last asked for, kept when the sprite has no such tag and `clip` is `None`
+    pub requested_clip: Option<String>,
+    /// set while a ping-pong clip is walking backwards
+    pub reversing: bool,
+    pub mode: PlayMode,
+    /// multiplies how fast time passes, 2.0 plays at double speed
+    pub speed: f32,
+    pub paused: bool,
+    /// set once a `PlayMode::Once` animation has played through and has nothing to follow it
+    pub finished: bool,
+    /// played, looping, when a `PlayMode::Once` clip finishes
+    pub next_clip: Option<String>,
+    /// which way the entity looks, picks between a clip's direction tags, see `Direction`
+    pub facing: Vec2,
+    /// draw mirrored, for sides that only have art for the other side
+    pub flip_x: bool,
+    /// the tag actually playing, `clip` or the one of its direction tags for `facing`
+    pub playing_tag: Option<String>,
+    /// set when a clip starts, the next step reports the events on the frame it started on
+    pub entering: bool,
 }
 
 impl SpriteAnimator {
-    pub fn new(sprite: Sprite) -> Self {
+    /// Starts at the sprite's default scale.
+    pub fn new(sprite: Sprite, sprites: &[SpriteData]) -> Self {
         Self {
             sprite,
             current_frame: 0,
             current_time: 0.0,
-            scale: 1.0,
+            scale: sprites[sprite.index()].defaults.scale,
+            clip: None,
+            requested_clip: None,
+            reversing: false,
+            mode: PlayMode::Loop,
+            speed: 1.0,
+            paused: false,
+            finished: false,
+            next_clip: None,
+            facing: Direction::Down.vector(),
+            flip_x: false,
+            playing_tag: None,
+            entering: false,
         }
     }
 
+    /// Switches to another sprite, playing all of it on a loop. Speed and pause carry over.
     pub fn set_sprite(&mut self, sprite: Sprite) {
         // reset if the sprite changes
         if self.sprite != sprite {
             self.current_frame = 0;
             self.current_time = 0.0;
+            self.clip = None;
+            self.requested_clip = None;
+            self.reversing = false;
+            self.mode = PlayMode::Loop;
+            self.finished = false;
+            self.next_clip = None;
+            self.playing_tag = None;
+            self.entering = true;
         }
         self.sprite = sprite;
     }
 
+    /// Turns towards `direction`, ignored if it is zero so standing still keeps the last way.
+    pub fn face(&mut self, direction: Vec2) {
+        if direction != Vec2::ZERO {
+            self.facing = direction;
+        }
+    }
+
     pub fn get_sprite(&self) -> Sprite {
         self.sprite
     }
 
-    pub fn step(&mut self, sprites: &[SpriteData], dt: f32) {
-        let sprite_data = &sprites[self.sprite as usize];
-        let frame = &sprite_data.frames[self.current_frame];
-        self.current_time += dt;
-        if self.current_time >= frame.duration {
-            self.current_time = 0.0;
-            self.current_frame = (self.current_frame + 1) % sprite_data.frames.len();
+    /// Loops the tag called `clip` from its first frame, unless it is already playing.
+    /// If the sprite has no such tag the whole sheet is played instead.
+    pub fn play_clip(&mut self, sprites: &[SpriteData], clip: &str) {
+        if self.requested_clip.as_deref() == Some(clip)
+            && self.mode == PlayMode::Loop
+            && !self.finished
+        {
+            return;
+        }
+        self.mode = PlayMode::Loop;
+        self.next_clip = None;
+        self.start_clip(&sprites[self.sprite.index()], Some(clip));
+    }
+
+    /// Plays the tag called `clip` through once from its first frame, then loops `then`
+    /// if given, otherwise stops and sets `finished`.
+    pub fn play_clip_once(&mut self, sprites: &[SpriteData], clip: &str, then: Option<&str>) {
+        self.mode = PlayMode::Once;
+        self.next_clip = then.map(|then| then.to_string());
+        self.start_clip(&sprites[self.sprite.index()], Some(clip));
+    }
+
+    /// Plays whatever is playing again from its first frame.
+    pub fn restart(&mut self, sprites: &[SpriteData]) {
+        let clip = self.requested_clip.clone();
+        self.start_clip(&sprites[self.sprite.index()], clip.as_deref());
+    }
+
+    fn start_clip(&mut self, sprite_data: &SpriteData, clip: Option<&str>) {
+        self.requested_clip = clip.map(|clip| clip.to_string());
+        self.clip = clip
+            .filter(|clip| has_clip(sprite_data, clip))
+            .map(|clip| clip.to_string());
+        self.playing_tag = None;
+        self.face_tag(sprite_data);
+        let (from, to, direction) = self.playback_range(sprite_data);
+        self.reversing = direction.starts_reversed();
+        self.current_frame = if self.reversing { to } else { from };
+        self.current_time = 0.0;
+        self.finished = false;
+        self.entering = true;
+    }
+
+    pub fn get_clip(&self) -> Option<&str> {
+        self.clip.as_deref()
+    }
+
+    /// The tag to play for `clip` facing `facing`, and whether it has to be mirrored.
+    /// The closest drawn direction wins, or the closest mirrored one, drawn first on a tie.
+    /// Without direction tags the sidecar's `faces` says when to mirror.
+    fn facing_tag<'a>(&self, sprite_data: &'a SpriteData) -> (Option<&'a Tag>, bool) {
+        let facing = self.facing.normalize_or(Direction::Down.vector());
+        let mut best: Option<(&Tag, bool, f32)> = None;
+        for direction in Direction::ALL {
+            let Some(tag) = sprite_data.get_tag(&direction.tag_name(self.clip.as_deref())) else {
+                continue;
+            };
+            for (shown, flip) in [(direction, false), (direction.mirrored(), true)] {
+                let score = shown.vector().dot(facing) - if flip { 0.001 } else { 0.0 };
+                if best.is_none_or(|(_, _, best_score)| score > best_score) {
+                    best = Some((tag, flip, score));
+                }
+            }
+        }
+        if let Some((tag, flip, _)) = best {
+            return (Some(tag), flip);
+        }
+
+        let tag = self
+            .clip
+            .as_deref()
+            .and_then(|clip| sprite_data.get_tag(clip));
+        let flip = match sprite_data.defaults.faces {
+            Some(faces) if facing.x != 0.0 => faces.vector().x * facing.x < 0.0,
+            _ => self.flip_x,
+        };
+        (tag, flip)
+    }
+
+    /// Switches to the tag for the way the animator faces, at the same point in the clip.
+    fn face_tag(&mut self, sprite_data: &SpriteData) {
+        let (tag, flip) = self.facing_tag(sprite_data);
+        self.flip_x = flip;
+        let tag_name = tag.map(|tag| tag.name.as_str());
+        if tag_name == self.playing_tag.as_deref() {
+            return;
+        }
+        let (old_from, _, _) = self.playback_range(sprite_data);
+        let offset = self.current_frame.saturating_sub(old_from);
+        self.playing_tag = tag_name.map(|name| name.to_string());
+        let (from, to, _) = self.playback_range(sprite_data);
+        self.current_frame = (from + offset).min(to);
+    }
+
+    /// The inclusive frame range and direction currently being played.
+    fn playback_range(&self, sprite_data: &SpriteData) -> (usize, usize, AnimationDirection) {
+        let tag = self
+            .playing_tag
+            .as_deref()
+            .and_then(|tag| sprite_data.get_tag(tag));
+        let (from, to, direction) = match tag {
+            Some(tag) => (tag.from, tag.to, tag.direction),
+            None => (0, sprite_data.frames.len() - 1, AnimationDirection::Forward),
+        };
+        let direction = match self.mode {
+            PlayMode::Loop | PlayMode::Once => direction,
+            PlayMode::PingPong => AnimationDirection::PingPong,
+            PlayMode::Reverse => AnimationDirection::Reverse,
+        };
+        (from, to, direction)
+    }
+
+    /// Moves to the next frame, returns false instead when a `PlayMode::Once`
+    /// animation is on its last frame.
+    fn advance_frame(&mut self, from: usize, to: usize, direction: AnimationDirection) -> bool {
+        let once = self.mode == PlayMode::Once;
+        match direction {
+            AnimationDirection::Forward => {
+                if self.current_frame >= to {
+                    if once {
+                        return false;
+                    }
+                    self.current_frame = from;
+                } else {
+                    self.current_frame += 1;
+                }
+            }
+            AnimationDirection::Reverse => {
+                if self.current_frame <= from {
+                    if once {
+                        return false;
+                    }
+                    self.current_frame = to;
+                } else {
+                    self.current_frame -= 1;
+                }
+            }
+            AnimationDirection::PingPong | AnimationDirection::PingPongReverse => {
+                // once is there and back, ending where it started
+                let started_reversed = direction.starts_reversed();
+                let back_at_start = if started_reversed {
+                    !self.reversing && self.current_frame >= to
+                } else {
+                    self.reversing && self.current_frame <= from
+                };
+                if once && (from == to || back_at_start) {
+                    return false;
+                }
+                if from == to {
+                    return true;
+                }
+                if self.reversing && self.current_frame <= from {
+                    self.reversing = false;
+                } else if !self.reversing && self.current_frame >= to {
+                    self.reversing = true;
+                }
+                if self.reversing {
+                    self.current_frame -= 1;
+                } else {
+                    self.current_frame += 1;
+                }
+            }
+        }
+        true
+    }
+
+    /// Plays `dt` ms of the animation, scaled by `speed`. Leftover time carries into the
+    /// next frame, so a long step can move several frames. Returns the events on every
+    /// frame it moved onto, in order, starting with the first frame of a clip just started.
+    pub fn step<'a>(&mut self, sprites: &'a [SpriteData], dt: f32) -> Vec<&'a str> {
+        let mut events = vec![];
+        let sprite_data = &sprites[self.sprite.index()];
+        self.face_tag(sprite_data);
+        if self.paused || self.finished {
+            return events;
         }
+        let (mut from, mut to, mut direction) = self.playback_range(sprite_data);
+        if self.current_frame < from || self.current_frame > to {
+            self.current_frame = from;
+        }
+        if self.entering {
+            self.entering = false;
+            events.extend(sprite_data.events_on(self.current_frame));
+        }
+        self.current_time += dt * self.speed.max(0.0);
+        loop {
+            // a zero length frame would never let the loop end
+            let duration = sprite_data.frames[self.current_frame].duration.max(1.0);
+            if self.current_time < duration {
+                break;
+            }
+            self.current_time -= duration;
+            if !self.advance_frame(from, to, direction) {
+                match self.next_clip.take() {
+                    Some(next_clip) => {
+                        let leftover = self.current_time;
+                        self.mode = PlayMode::Loop;
+                        self.start_clip(sprite_data, Some(&next_clip));
+                        // its first frame's events are reported just below
+                        self.entering = false;
+                        self.current_time = leftover;
+                        (from, to, direction) = self.playback_range(sprite_data);
+                    }
+                    None => {
+                        self.finished = true;
+                        self.current_time = 0.0;
+                        break;
+                    }
+                }
+            }
+            events.extend(sprite_data.events_on(self.current_frame));
+        }
+        events
+    }
+
+    /// Fixes up playback after the sprite was reloaded. A clip that no longer exists
+    /// falls back to the whole sheet, and the frame is clamped into what is left.
+    pub fn refresh(&mut self, sprites: &[SpriteData]) {
+        let sprite_data = &sprites[self.sprite.index()];
+        if let Some(clip) = self.clip.as_deref() {
+            if !has_clip(sprite_data, clip) {
+                self.clip = None;
+                self.reversing = false;
+            }
+        }
+        if let Some(tag) = self.playing_tag.as_deref() {
+            if sprite_data.get_tag(tag).is_none() {
+                self.playing_tag = None;
+            }
+        }
+        self.face_tag(sprite_data);
+        let (from, to, _) = self.playback_range(sprite_data);
+        self.current_frame = self.current_frame.clamp(from, to);
     }
 
     pub fn randomize_frame(&mut self, sprites: &[SpriteData]) {
-        let sprite_data = &sprites[self.sprite as usize];
-        self.current_frame = rand::thread_rng().gen_range(0..sprite_data.frames.len());
+        let sprite_data = &sprites[self.sprite.index()];
+        let (from, to, _) = self.playback_range(sprite_data);
+        self.current_frame = rand::thread_rng().gen_range(from..=to);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// An untrimmed 8x8 frame at `x` on the sheet.
+    fn frame_at(x: u32) -> Value {
+        json!({ "frame": { "x": x, "y": 0, "w": 8, "h": 8 }, "duration": 100 })
+    }
+
+    fn frame_xs(frames: &Value) -> Vec<u64> {
+        ordered_frames(frames, JsonContext::new(Path::new("x.json")))
+            .unwrap()
+            .iter()
+            .map(|(_, frame)| frame["frame"]["x"].as_u64().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn hash_frames_sort_by_number() {
+        let frames = json!({
+            "x 10.aseprite": frame_at(80),
+            "x 2.aseprite": frame_at(16),
+            "x 0.aseprite": frame_at(0),
+            "x 1.aseprite": frame_at(8),
+            "x 9.aseprite": frame_at(72),
+        });
+        assert_eq!(frame_xs(&frames), vec![0, 8, 16, 72, 80]);
+    }
+
+    #[test]
+    fn hash_frames_without_numbers_keep_file_order() {
+        let frames = json!({
+            "b": frame_at(8),
+            "a 1": frame_at(0),
+        });
+        assert_eq!(frame_xs(&frames), vec![8, 0]);
+    }
+
+    #[test]
+    fn array_frames_keep_file_order() {
+        let frames = json!([frame_at(16), frame_at(0), frame_at(8)]);
+        assert_eq!(frame_xs(&frames), vec![16, 0, 8]);
+    }
+
+    #[test]
+    fn frame_numbers() {
+        assert_eq!(frame_number("man_walk 10.aseprite"), Some(10));
+        assert_eq!(frame_number("man_walk 2"), Some(2));
+        assert_eq!(frame_number("tree.aseprite"), None);
+        assert_eq!(frame_number("7.png"), Some(7));
+    }
+
+    #[test]
+    fn straight_directions_are_exact() {
+        assert_eq!(Direction::Right.vector(), Vec2::new(1.0, 0.0));
+        assert_eq!(Direction::Down.vector(), Vec2::new(0.0, 1.0));
+        assert_eq!(Direction::Left.vector(), Vec2::new(-1.0, 0.0));
+        assert_eq!(Direction::Up.vector(), Vec2::new(0.0, -1.0));
+        for direction in Direction::ALL {
+            assert!((direction.vector().length() - 1.0).abs() < 1e-6);
+            assert_eq!(direction.mirrored().vector().x, -direction.vector().x);
+        }
+    }
+
+    #[test]
+    fn sprites_facing_a_side_only_mirror_to_face_the_other() {
+        let json = json!({
+            "frames": [frame_at(0), frame_at(8)],
+            "defaults": { "faces": "right" },
+        });
+        let sprites = vec![sprite_data_from_json(&json, Path::new("x.json")).unwrap()];
+        let mut animator = SpriteAnimator::new(Sprite(0), &sprites);
+        assert!(!animator.flip_x);
+
+        animator.face(Vec2::new(-5.0, 0.0));
+        animator.step(&sprites, 0.0);
+        assert!(animator.flip_x);
+        // straight down keeps looking the way it was
+        animator.face(Vec2::new(0.0, 5.0));
+        animator.step(&sprites, 0.0);
+        assert!(animator.flip_x);
+        animator.face(Vec2::new(5.0, 5.0));
+        animator.step(&sprites, 0.0);
+        assert!(!animator.flip_x);
+    }
+
+    fn tags(count: usize, frame_tags: Value) -> Result<Vec<Tag>, AssetError> {
+        load_frame_tags(
+            &json!({ "meta": { "frameTags": frame_tags } }),
+            count,
+            JsonContext::new(Path::new("x.json")),
+        )
+    }
+
+    /// `count` 8x8 frames of 100ms in a row, with the given tags.
+    fn sheet(count: u32, tags: Vec<Tag>) -> SpriteData {
+        SpriteData {
+            tags,
+            ..SpriteData::strip(count, UVec2::splat(8))
+        }
+    }
+
+    #[test]
+    fn frame_tags_become_clips() {
+        let data = sheet(
+            4,
+            tags(
+                4,
+                json!([
+                    { "name": "idle", "from": 0, "to": 0 },
+                    { "name": "walk", "from": 1, "to": 3, "direction": "pingpong" },
+                ]),
+            )
+            .unwrap(),
+        );
+        let walk = data.get_tag("walk").unwrap();
+        assert_eq!((walk.from, walk.to), (1, 3));
+        assert_eq!(walk.direction, AnimationDirection::PingPong);
+        assert_eq!(
+            data.get_tag("idle").unwrap().direction,
+            AnimationDirection::Forward
+        );
+        assert!(data.get_tag("run").is_none());
+        let ctx = JsonContext::new(Path::new("x.json"));
+        assert!(load_frame_tags(&json!({}), 4, ctx).unwrap().is_empty());
+    }
+
+    #[test]
+    fn bad_frame_tags_are_rejected() {
+        let past_the_end = json!([{ "name": "walk", "from": 1, "to": 4 }]);
+        let backwards = json!([{ "name": "walk", "from": 2, "to": 1 }]);
+        let direction = json!([{ "name": "walk", "from": 0, "to": 1, "direction": "sideways" }]);
+        let unnamed = json!([{ "from": 0, "to": 1 }]);
+        for frame_tags in [past_the_end, backwards, direction, unnamed] {
+            assert!(matches!(
+                tags(4, frame_tags),
+                Err(AssetError::InvalidField { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn slice_keys_last_until_the_next_one() {
+        let key = |frame: usize, x: i32| SliceKey {
+            frame,
+            bounds: PixelRect {
+                position: IVec2::new(x, 0),
+                size: UVec2::splat(4),
+            },
+            center: None,
+            pivot: None,
+        };
+        let slice = Slice {
+            name: "hand".to_string(),
+            keys: vec![key(1, 10), key(3, 30)],
+            data: None,
+        };
+        let x_at = |frame| slice.key_at(frame).map(|key| key.bounds.position.x);
+        assert_eq!(x_at(0), None);
+        assert_eq!(x_at(1), Some(10));
+        assert_eq!(x_at(2), Some(10));
+        assert_eq!(x_at(3), Some(30));
+        assert_eq!(x_at(7), Some(30));
+    }
+
+    #[test]
+    fn play_clip_starts_at_the_tag() {
+        let walk = tags(4, json!([{ "name": "walk", "from": 2, "to": 3 }])).unwrap();
+        let sprites = vec![sheet(4, walk)];
+        let mut animator = SpriteAnimator::new(Sprite(0), &sprites);
+        animator.play_clip(&sprites, "walk");
+        assert_eq!(animator.get_clip(), Some("walk"));
+        assert_eq!(animator.current_frame, 2);
+        animator.step(&sprites, 100.0);
+        animator.step(&sprites, 100.0);
+        assert_eq!(animator.current_frame, 2);
+
+        // playing it again doesn't restart it
+        animator.step(&sprites, 100.0);
+        animator.play_clip(&sprites, "walk");
+        assert_eq!(animator.current_frame, 3);
+
+        // no such tag plays the whole sheet
+        animator.play_clip(&sprites, "run");
+        assert_eq!(animator.get_clip(), None);
+        assert_eq!(animator.current_frame, 0);
+    }
+
+    #[test]
+    fn playing_a_missing_tag_again_keeps_going() {
+        let walk = tags(4, json!([{ "name": "walk", "from": 2, "to": 3 }])).unwrap();
+        let sprites = vec![sheet(4, walk)];
+        let mut animator = SpriteAnimator::new(Sprite(0), &sprites);
+        animator.play_clip(&sprites, "run");
+        animator.step(&sprites, 100.0);
+        animator.play_clip(&sprites, "run");
+        assert_eq!(animator.current_frame, 1);
+        animator.step(&sprites, 100.0);
+        animator.play_clip(&sprites, "run");
+        assert_eq!(animator.current_frame, 2);
+    }
+
+    #[test]
+    fn refresh_keeps_animators_inside_a_smaller_sheet() {
+        let walk = tags(6, json!([{ "name": "walk", "from": 2, "to": 5 }])).unwrap();
+        let sprites = vec![sheet(6, walk)];
+        let mut animator = SpriteAnimator::new(Sprite(0), &sprites);
+        animator.play_clip(&sprites, "walk");
+        animator.step(&sprites, 300.0);
+        assert_eq!(animator.current_frame, 5);
+
+        // the tag is still there, just shorter
+        let walk = tags(3, json!([{ "name": "walk", "from": 1, "to": 2 }])).unwrap();
+        let sprites = vec![sheet(3, walk)];
+        animator.refresh(&sprites);
+        assert_eq!(animator.get_clip(), Some("walk"));
+        assert_eq!(animator.current_frame, 2);
+        animator.step(&sprites, 100.0);
+        assert_eq!(animator.current_frame, 1);
+
+        // the tag is gone, the whole sheet plays instead
+        let sprites = vec![sheet(2, vec![])];
+        animator.current_frame = 2;
+        animator.refresh(&sprites);
+        assert_eq!(animator.get_clip(), None);
+        assert_eq!(animator.current_frame, 1);
+        animator.step(&sprites, 100.0);
+        assert_eq!(animator.current_frame, 0);
+    }
+
+    /// The frames an animator is on after each of `steps` 100ms steps.
+    fn frames_stepped(
+        animator: &mut SpriteAnimator,
+        sprites: &[SpriteData],
+        steps: usize,
+    ) -> Vec<usize> {
+        (0..steps)
+            .map(|_| {
+                animator.step(sprites, 100.0);
+                animator.current_frame
+            })
+            .collect()
+    }
+
+    #[test]
+    fn once_stops_on_the_last_frame() {
+        let swing = tags(5, json!([{ "name": "swing", "from": 1, "to": 3 }])).unwrap();
+        let sprites = vec![sheet(5, swing)];
+        let mut animator = SpriteAnimator::new(Sprite(0), &sprites);
+        animator.play_clip_once(&sprites, "swing", None);
+        assert_eq!(animator.current_frame, 1);
+        animator.step(&sprites, 100.0);
+        assert!(!animator.finished);
+
+        animator.step(&sprites, 250.0);
+        assert_eq!(animator.current_frame, 3);
+        assert!(animator.finished);
+        assert_eq!(frames_stepped(&mut animator, &sprites, 2), [3, 3]);
+
+        // playing it again starts over
+        animator.play_clip_once(&sprites, "swing", None);
+        assert!(!animator.finished);
+        assert_eq!(animator.current_frame, 1);
+    }
+
+    #[test]
+    fn once_moves_on_to_then() {
+        let frame_tags = json!([
+            { "name": "swing", "from": 0, "to": 1 },
+            { "name": "idle", "from": 3, "to": 4 },
+        ]);
+        let sprites = vec![sheet(5, tags(5, frame_tags).unwrap())];
+        let mut animator = SpriteAnimator::new(Sprite(0), &sprites);
+        animator.play_clip_once(&sprites, "swing", Some("idle"));
+        assert_eq!(animator.get_clip(), Some("swing"));
+
+        // what is left of the step carries over into the next clip
+        animator.step(&sprites, 250.0);
+        assert_eq!(animator.get_clip(), Some("idle"));
+        assert_eq!(animator.mode, PlayMode::Loop);
+        assert_eq!(animator.current_frame, 3);
+        assert_eq!(animator.current_time, 50.0);
+        assert!(!animator.finished);
+        assert_eq!(frames_stepped(&mut animator, &sprites, 3), [4, 3, 4]);
+    }
+
+    #[test]
+    fn ping_pong_turns_around_at_both_ends() {
+        let frame_tags = json!([{ "name": "walk", "from": 1, "to": 3, "direction": "pingpong" }]);
+        let sprites = vec![sheet(5, tags(5, frame_tags).unwrap())];
+        let mut animator = SpriteAnimator::new(Sprite(0), &sprites);
+        animator.play_clip(&sprites, "walk");
+        assert_eq!(
+            frames_stepped(&mut animator, &sprites, 6),
+            [2, 3, 2, 1, 2, 3]
+        );
+
+        // the mode does the same with a tag that only goes forwards
+        let sprites = vec![sheet(3, vec![])];
+        let mut animator = SpriteAnimator::new(Sprite(0), &sprites);
+        animator.mode = PlayMode::PingPong;
+        assert_eq!(frames_stepped(&mut animator, &sprites, 5), [1, 2, 1, 0, 1]);
+        animator.step(&sprites, 100.0);
+        assert!(!animator.reversing);
+        animator.step(&sprites, 100.0);
+        assert!(animator.reversing);
+
+        animator.mode = PlayMode::Reverse;
+        animator.restart(&sprites);
+        assert_eq!(animator.current_frame, 2);
+        assert_eq!(frames_stepped(&mut animator, &sprites, 3), [1, 0, 2]);
+    }
+
+    #[test]
+    fn stopped_animators_stay_put() {
+        let sprites = vec![sheet(4, vec![])];
+        let mut animator = SpriteAnimator::new(Sprite(0), &sprites);
+        animator.speed = 0.0;
+        animator.step(&sprites, 1000.0);
+        assert_eq!(animator.current_frame, 0);
+
+        animator.speed = 1.0;
+        animator.paused = true;
+        animator.step(&sprites, 1000.0);
+        assert_eq!(animator.current_frame, 0);
+
+        animator.paused = false;
+        animator.step(&sprites, 100.0);
+        assert_eq!(animator.current_frame, 1);
+    }
+
+    #[test]
+    fn long_steps_move_several_frames() {
+        let sprites = vec![sheet(5, vec![])];
+        let mut animator = SpriteAnimator::new(Sprite(0), &sprites);
+        animator.step(&sprites, 350.0);
+        assert_eq!(animator.current_frame, 3);
+        assert_eq!(animator.current_time, 50.0);
+        // and wrap around
+        animator.step(&sprites, 250.0);
+        assert_eq!(animator.current_frame, 1);
+
+        animator.speed = 2.0;
+        animator.step(&sprites, 100.0);
+        assert_eq!(animator.current_frame, 3);
+    }
+
+    #[test]
+    fn starting_a_clip_reports_its_first_frame() {
+        let walk = tags(3, json!([{ "name": "walk", "from": 1, "to": 2 }])).unwrap();
+        let events = [(1, "footstep"), (2, "clap")].map(|(frame, name)| FrameEvent {
+            frame,
+            name: name.to_string(),
+        });
+        let sprites = vec![SpriteData {
+            events: events.to_vec(),
+            ..sheet(3, walk)
+        }];
+        let mut animator = SpriteAnimator::new(Sprite(0), &sprites);
+        animator.play_clip(&sprites, "walk");
+        assert_eq!(animator.step(&sprites, 0.0), vec!["footstep"]);
+        assert!(animator.step(&sprites, 0.0).is_empty());
+        assert_eq!(animator.step(&sprites, 100.0), vec!["clap"]);
+
+        // a long first step reports the start and every frame it moves onto
+        animator.restart(&sprites);
+        assert_eq!(
+            animator.step(&sprites, 200.0),
+            vec!["footstep", "clap", "footstep"]
+        );
     }
 }
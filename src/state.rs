@@ -1,34 +1,135 @@
-use glam::Vec2;
-use rand::Rng;
+use crate::entity::{Entity, EntityId, Role};
 
-use crate::{
-    entity::{Entity, EntityType},
-    settings::SCREEN_DIMS,
-    sprite::{Sprite, SpriteAnimator, SpriteData},
-};
+/// A frame event an entity's animation reached, see `SpriteData::events`.
+#[derive(Clone, Debug)]
+pub struct AnimationEvent {
+    pub entity: EntityId,
+    pub name: String,
+}
+
+struct Slot {
+    /// bumped every time the slot's entity is removed
+    generation: u32,
+    entity: Option<Entity>,
+}
 
 pub struct State {
-    pub entities: Vec<Entity>,
+    slots: Vec<Slot>,
+    /// empty slots, reused before new ones are made
+    free_slots: Vec<u32>,
     pub chop_cooldown: f32,
+    /// what the animations reached during the last `step_sprites`
+    pub animation_events: Vec<AnimationEvent>,
 }
 
 impl State {
     pub const CHOP_COOLDOWN: f32 = 0.2;
     pub fn new() -> Self {
         Self {
-            entities: vec![],
+            slots: vec![],
+            free_slots: vec![],
             chop_cooldown: 0.0,
+            animation_events: vec![],
         }
     }
 
-    pub fn add_entity(&mut self, entity: Entity) -> usize {
+    pub fn add_entity(&mut self, entity: Entity) -> EntityId {
         // println!("Added entity: {:?}", entity);
-        self.entities.push(entity);
-        self.entities.len() - 1
+        match self.free_slots.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index as usize];
+                slot.entity = Some(entity);
+                EntityId {
+                    index,
+                    generation: slot.generation,
+                }
+            }
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    entity: Some(entity),
+                });
+                EntityId {
+                    index: (self.slots.len() - 1) as u32,
+                    generation: 0,
+                }
+            }
+        }
+    }
+
+    /// `None` if the entity has been removed.
+    pub fn get(&self, id: EntityId) -> Option<&Entity> {
+        let slot = self.slots.get(id.index as usize)?;
+        if slot.generation != id.generation {
+            return None;
+        }
+        slot.entity.as_ref()
+    }
+
+    pub fn get_mut(&mut self, id: EntityId) -> Option<&mut Entity> {
+        let slot = self.slots.get_mut(id.index as usize)?;
+        if slot.generation != id.generation {
+            return None;
+        }
+        slot.entity.as_mut()
+    }
+
+    pub fn contains(&self, id: EntityId) -> bool {
+        self.get(id).is_some()
+    }
+
+    /// Takes the entity out, every handle to it stops working.
+    pub fn remove(&mut self, id: EntityId) -> Option<Entity> {
+        let slot = self.slots.get_mut(id.index as usize)?;
+        if slot.generation != id.generation {
+            return None;
+        }
+        let entity = slot.entity.take()?;
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free_slots.push(id.index);
+        Some(entity)
+    }
+
+    /// Every entity, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (EntityId, &Entity)> {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            let id = EntityId {
+                index: index as u32,
+                generation: slot.generation,
+            };
+            slot.entity.as_ref().map(|entity| (id, entity))
+        })
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (EntityId, &mut Entity)> {
+        self.slots
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(index, slot)| {
+                let id = EntityId {
+                    index: index as u32,
+                    generation: slot.generation,
+                };
+                slot.entity.as_mut().map(|entity| (id, entity))
+            })
+    }
+
+    /// Every entity with the role, in no particular order.
+    pub fn with_role(&self, role: Role) -> impl Iterator<Item = (EntityId, &Entity)> {
+        self.iter().filter(move |(_, entity)| entity.has_role(role))
     }
 
-    pub fn get_entity(&self, id: usize) -> Option<&Entity> {
-        self.entities.get(id)
+    /// Collected, so the entities can be changed while going through them.
+    pub fn ids_with_role(&self, role: Role) -> Vec<EntityId> {
+        self.with_role(role).map(|(id, _)| id).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.free_slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 }
 
@@ -37,3 +138,82 @@ impl Default for State {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use glam::{UVec2, Vec2};
+
+    use super::*;
+    use crate::{
+        entity::{EntityType, Role},
+        sprite::{SpriteData, SpriteRegistry},
+    };
+
+    fn sprites() -> (SpriteRegistry, Vec<SpriteData>) {
+        let mut registry = SpriteRegistry::new();
+        registry.register("dot");
+        (registry, vec![SpriteData::strip(1, UVec2::splat(8))])
+    }
+
+    fn entity_at(x: f32, registry: &SpriteRegistry, sprites: &[SpriteData]) -> Entity {
+        let dot = registry.lookup("dot").unwrap();
+        Entity::new(EntityType::Log, Vec2::new(x, 0.0), dot, sprites)
+    }
+
+    #[test]
+    fn removed_ids_stay_dead_when_the_slot_is_reused() {
+        let (registry, sprites) = sprites();
+        let mut state = State::new();
+        let a = state.add_entity(entity_at(1.0, &registry, &sprites));
+        let b = state.add_entity(entity_at(2.0, &registry, &sprites));
+
+        assert_eq!(state.remove(a).unwrap().position.x, 1.0);
+        let c = state.add_entity(entity_at(3.0, &registry, &sprites));
+        assert_ne!(a, c);
+
+        assert!(state.get(a).is_none());
+        assert!(state.get_mut(a).is_none());
+        assert!(!state.contains(a));
+        assert!(state.remove(a).is_none());
+
+        assert_eq!(state.get(b).unwrap().position.x, 2.0);
+        assert_eq!(state.get(c).unwrap().position.x, 3.0);
+        assert_eq!(state.len(), 2);
+        let mut xs: Vec<f32> = state.iter().map(|(_, entity)| entity.position.x).collect();
+        xs.sort_by(f32::total_cmp);
+        assert_eq!(xs, vec![2.0, 3.0]);
+    }
+
+    #[test]
+    fn roles_stay_with_their_entities_when_slots_are_reused() {
+        let (registry, sprites) = sprites();
+        let with_roles = |x: f32, roles: Vec<Role>| Entity {
+            roles,
+            ..entity_at(x, &registry, &sprites)
+        };
+        let mut state = State::new();
+        let cursor = state.add_entity(with_roles(1.0, vec![Role::Cursor]));
+        let pickup = state.add_entity(with_roles(2.0, vec![Role::Pickup]));
+        let log = state.add_entity(with_roles(3.0, vec![]));
+        assert_eq!(state.ids_with_role(Role::Pickup), vec![pickup]);
+
+        state.remove(pickup);
+        assert!(state.ids_with_role(Role::Pickup).is_empty());
+        // a log takes the pickup's slot without becoming a pickup
+        let stump = state.add_entity(with_roles(4.0, vec![]));
+        assert_eq!(stump.index, pickup.index);
+        assert!(state.ids_with_role(Role::Pickup).is_empty());
+        let new_pickup = state.add_entity(with_roles(5.0, vec![Role::Pickup]));
+        assert_eq!(state.ids_with_role(Role::Pickup), vec![new_pickup]);
+
+        assert_eq!(state.ids_with_role(Role::Cursor), vec![cursor]);
+        let cursors: Vec<f32> = state
+            .with_role(Role::Cursor)
+            .map(|(_, entity)| entity.position.x)
+            .collect();
+        assert_eq!(cursors, vec![1.0]);
+        assert_eq!(state.get(log).unwrap().position.x, 3.0);
+        assert_eq!(state.get(stump).unwrap().position.x, 4.0);
+        assert!(state.get(pickup).is_none());
+    }
+}
@@ -1,24 +1,35 @@
 use glam::Vec2;
 use rand::Rng;
-use raylib::{ffi::MouseButton, RaylibHandle};
+use raylib::{color::Color, ffi::MouseButton, RaylibHandle};
 
 use crate::{
+    animation::AnimationGraph,
     audio::{Audio, SoundEffect},
-    entity::{Entity, EntityType},
+    entity::{Entity, EntityId, EntityType, Role},
+    game_sprites::GameSprites,
     graphics::Graphics,
     settings::SCREEN_DIMS,
-    sprite::{Sprite, SpriteAnimator, SpriteData},
-    state::State,
+    sprite::SpriteData,
+    state::{AnimationEvent, State},
 };
 
+/// Frame event on the axe swing where it hits.
+const IMPACT_EVENT: &str = "impact";
+/// Frame event on walk cycles where a foot lands.
+const FOOTSTEP_EVENT: &str = "footstep";
+/// How long something flashes and tilts after being hit.
+const HIT_FLASH_SECONDS: f32 = 0.3;
+/// Expiring entities fade out over their last this many seconds.
+const EXPIRE_FADE_SECONDS: f32 = 1.0;
+
 pub fn step_positions(state: &mut State, dt: f32) {
-    for entity in &mut state.entities {
+    for (_, entity) in state.iter_mut() {
         entity.position += entity.velocity * dt;
     }
 }
 
 pub fn wrap_around_screen(state: &mut State) {
-    for entity in &mut state.entities {
+    for (_, entity) in state.iter_mut() {
         if entity.position.x < 0.0 {
             entity.position.x += SCREEN_DIMS.x as f32;
         } else if entity.position.x >= SCREEN_DIMS.x as f32 {
@@ -32,197 +43,283 @@ pub fn wrap_around_screen(state: &mut State) {
     }
 }
 
-pub fn set_man_sprite_based_on_velocity(state: &mut State) {
-    // set sprite based on velocity
-    const MIN_WALK_SPEED: f32 = 10.0;
-    for entity in &mut state.entities {
-        if entity.entity_type != EntityType::Man {
-            continue;
+/// Sprites look the way they are moving.
+pub fn face_velocity(state: &mut State) {
+    for (_, entity) in state.iter_mut() {
+        entity.sprite_animator.face(entity.velocity);
+    }
+}
+
+/// Params every animation graph can use.
+pub fn set_movement_params(state: &mut State) {
+    for (_, entity) in state.iter_mut() {
+        if let Some(animation) = &mut entity.animation {
+            animation.set_param("speed", entity.velocity.length());
         }
-        let sprite = if entity.velocity.length() > MIN_WALK_SPEED {
-            Sprite::ManWalk
-        } else {
-            Sprite::ManIdle
-        };
-        if entity.sprite_animator.sprite != sprite {
-            entity.sprite_animator.set_sprite(sprite);
+    }
+}
+
+pub fn step_animation_states(
+    state: &mut State,
+    animations: &[AnimationGraph],
+    sprites: &[SpriteData],
+) {
+    for (_, entity) in state.iter_mut() {
+        if let Some(animation) = &mut entity.animation {
+            animation.step(animations, &mut entity.sprite_animator, sprites);
         }
     }
 }
 
 pub fn step_sprites(state: &mut State, sprites: &[SpriteData], dt: f32) {
     let dt_ms = dt * 1000.0;
-    for entity in &mut state.entities {
-        entity.sprite_animator.step(sprites, dt_ms);
+    state.animation_events.clear();
+    let mut events = vec![];
+    for (id, entity) in state.iter_mut() {
+        for name in entity.sprite_animator.step(sprites, dt_ms) {
+            events.push(AnimationEvent {
+                entity: id,
+                name: name.to_string(),
+            });
+        }
     }
+    state.animation_events = events;
 }
 
-pub fn do_touch_apple(state: &mut State, audio: &mut Audio) {
-    // if entity 02, touches the apple [entity 01], spawn a new man at the end of the list, make him follow the last entity in the list
-    // consider the apple scale
-    let apple = &state.entities[1];
-    let apple_bounds = apple.get_bounds();
-    for entity in &mut state.entities {
-        if entity.entity_type != EntityType::Man {
+pub fn play_footsteps(state: &State, audio: &mut Audio) {
+    let rng = &mut rand::thread_rng();
+    for event in &state.animation_events {
+        if event.name != FOOTSTEP_EVENT {
             continue;
         }
-        let man = entity;
-        let man_bounds = man.get_bounds();
-
-        if man_bounds.intersects(&apple_bounds) {
-            // play sound
-            audio.play_sound_effect(SoundEffect::UiConfirm);
-
-            // find the latest man entity
-            let man_entity: Option<usize> = state
-                .entities
-                .iter()
-                .enumerate()
-                .filter(|(_, entity)| entity.entity_type == EntityType::Man)
-                .map(|(i, _)| i)
-                .last();
-
-            let pos = if let Some(man_entity) = man_entity {
-                state.entities[man_entity].position
-            } else {
-                Vec2::new(SCREEN_DIMS.x as f32 / 2.0, SCREEN_DIMS.y as f32 / 2.0)
-            };
+        let sound = if rng.gen_bool(0.5) {
+            SoundEffect::Step1
+        } else {
+            SoundEffect::Step2
+        };
+        audio.play_sound_effect(sound);
+    }
+}
 
-            let rng = &mut rand::thread_rng();
-            let max_scale = 10.0;
-            let scale = rng.gen_range(4.0..max_scale);
-            let base_size = Vec2::new(2.0, 4.0);
-            state.add_entity(Entity {
-                entity_type: EntityType::Man,
-                position: pos,
-                velocity: Vec2::new(0.0, 0.0),
-                size: base_size * scale,
-                sprite_animator: SpriteAnimator {
-                    sprite: Sprite::ManIdle,
-                    current_frame: 0,
-                    current_time: 0.0,
-                    scale,
-                },
-                follows: man_entity,
-                hp: 10.0,
-                active: true,
-                friction: None,
-                expire_in: None,
-            });
-            // move the apple to a new random position
-            let rng = &mut rand::thread_rng();
-            let new_pos = Vec2::new(
-                rng.gen_range(0.0..SCREEN_DIMS.x as f32),
-                rng.gen_range(0.0..SCREEN_DIMS.y as f32),
-            );
-            state.entities[1].position = new_pos;
-            break;
+pub fn fit_hitboxes_to_sprites(state: &mut State, sprites: &[SpriteData]) {
+    for (_, entity) in state.iter_mut() {
+        entity.fit_hitbox_to_sprite(sprites);
+    }
+}
+
+/// Call after the sprites were reloaded, their frames and clips may have changed.
+pub fn refresh_sprite_animators(state: &mut State, sprites: &[SpriteData]) {
+    for (_, entity) in state.iter_mut() {
+        entity.sprite_animator.refresh(sprites);
+    }
+}
+
+/// Call after the animation graphs were reloaded. Entities whose graph is gone keep
+/// the sprite they have and stop changing it.
+pub fn refresh_animation_states(
+    state: &mut State,
+    old_animations: &[AnimationGraph],
+    animations: &[AnimationGraph],
+    sprites: &[SpriteData],
+) {
+    for (_, entity) in state.iter_mut() {
+        if let Some(animation) = &mut entity.animation {
+            let animator = &mut entity.sprite_animator;
+            if !animation.refresh(old_animations, animations, animator, sprites) {
+                entity.animation = None;
+            }
         }
     }
 }
 
-/*
-    determine reticle sprite
-    // default reticle is reticle sprite
-    // if reticle is on top of a tree, make it AxeIdle,
-    // if currently clicking, make it AxeCutting,
-*/
-pub fn determine_reticle_sprite(rl: &mut RaylibHandle, state: &mut State, audio: &mut Audio) {
-    let reticle = &state.entities[0];
+/// Men touching a pickup get a new man following the newest one, and the pickup moves.
+pub fn do_touch_apple(
+    state: &mut State,
+    audio: &mut Audio,
+    graphics: &Graphics,
+    game_sprites: &GameSprites,
+    animations: &[AnimationGraph],
+) {
+    for pickup in state.ids_with_role(Role::Pickup) {
+        touch_pickup(state, pickup, audio, graphics, game_sprites, animations);
+    }
+}
+
+fn touch_pickup(
+    state: &mut State,
+    pickup: EntityId,
+    audio: &mut Audio,
+    graphics: &Graphics,
+    game_sprites: &GameSprites,
+    animations: &[AnimationGraph],
+) {
+    // consider the apple scale
+    let Some(apple) = state.get(pickup) else {
+        return;
+    };
+    let apple_bounds = apple.get_bounds();
+    let touched = state.iter().any(|(_, entity)| {
+        entity.entity_type == EntityType::Man && entity.get_bounds().intersects(&apple_bounds)
+    });
+    if !touched {
+        return;
+    }
+
+    // play sound
+    audio.play_sound_effect(SoundEffect::UiConfirm);
+
+    // the newest man is the one nobody follows yet
+    let men: Vec<EntityId> = state
+        .iter()
+        .filter(|(_, entity)| entity.entity_type == EntityType::Man)
+        .map(|(id, _)| id)
+        .collect();
+    let man_entity = men
+        .iter()
+        .copied()
+        .find(|man| !state.iter().any(|(_, entity)| entity.follows == Some(*man)));
+
+    let pos = match man_entity.and_then(|man| state.get(man)) {
+        Some(man) => man.position,
+        None => Vec2::new(SCREEN_DIMS.x as f32 / 2.0, SCREEN_DIMS.y as f32 / 2.0),
+    };
+
+    let rng = &mut rand::thread_rng();
+    let max_scale = 10.0;
+    // every new follower gets the next shirt colour
+    let man_idle = game_sprites.man_idle;
+    let palette = men.len() % graphics.get_sprite_data(man_idle).palette_count();
+    let mut man = Entity {
+        palette,
+        follows: man_entity,
+        ..Entity::new(EntityType::Man, pos, man_idle, &graphics.sprites)
+    };
+    // and a random size
+    man.set_scale(rng.gen_range(4.0..max_scale), &graphics.sprites);
+    // the graph can be gone after a reload, then the man just keeps standing
+    let _ = man.set_animation(animations, "man", &graphics.sprites);
+    state.add_entity(man);
+    // move the apple to a new random position
+    let new_pos = Vec2::new(
+        rng.gen_range(0.0..SCREEN_DIMS.x as f32),
+        rng.gen_range(0.0..SCREEN_DIMS.y as f32),
+    );
+    if let Some(apple) = state.get_mut(pickup) {
+        apple.position = new_pos;
+    }
+}
+
+/// Chops the trees under each cursor when its axe lands. The cursor's animation gets
+/// told whether it is over a tree and whether the mouse is down, it swings the axe.
+pub fn do_chopping(
+    rl: &mut RaylibHandle,
+    state: &mut State,
+    audio: &mut Audio,
+    graphics: &Graphics,
+    game_sprites: &GameSprites,
+) {
+    let clicking = rl.is_mouse_button_down(MouseButton::MOUSE_BUTTON_LEFT);
+    for cursor in state.ids_with_role(Role::Cursor) {
+        chop_under(state, cursor, clicking, audio, graphics, game_sprites);
+    }
+
+    if state.chop_cooldown > 0.0 {
+        state.chop_cooldown -= rl.get_frame_time();
+    }
+}
+
+fn chop_under(
+    state: &mut State,
+    reticle_id: EntityId,
+    clicking: bool,
+    audio: &mut Audio,
+    graphics: &Graphics,
+    game_sprites: &GameSprites,
+) {
+    let Some(reticle) = state.get(reticle_id) else {
+        return;
+    };
     let reticle_bounds = reticle.get_bounds();
-    let mut on_tree = false;
-    let mut trees: Vec<usize> = vec![];
-    for (i, entity) in state.entities.iter().enumerate() {
+    let mut trees: Vec<EntityId> = vec![];
+    for (id, entity) in state.iter() {
         if entity.entity_type != EntityType::Tree {
             continue;
         }
         let tree_bounds = entity.get_bounds();
 
         if reticle_bounds.intersects(&tree_bounds) {
-            on_tree = true;
-            trees.push(i);
+            trees.push(id);
         }
     }
 
-    let reticle = &mut state.entities[0];
-    let clicking = rl.is_mouse_button_down(MouseButton::MOUSE_BUTTON_LEFT);
+    if let Some(animation) = state
+        .get_mut(reticle_id)
+        .and_then(|reticle| reticle.animation.as_mut())
+    {
+        animation.set_flag("hovering", !trees.is_empty());
+        animation.set_flag("clicking", clicking);
+    }
 
-    if on_tree {
-        if clicking {
-            reticle.sprite_animator.set_sprite(Sprite::AxeCutting);
-
-            // if frame 3, hurt tree and play chop sound
-            if reticle.sprite_animator.current_frame == 2 {
-                if state.chop_cooldown > 0.0 {
-                    return;
-                }
-                state.chop_cooldown = State::CHOP_COOLDOWN;
-                audio.play_sound_effect(SoundEffect::BaseballBatSwing);
-
-                for tree in trees {
-                    if state.entities[tree].hp > 0.0 {
-                        state.entities[tree].hp -= 1.0;
-                    }
-
-                    // spawn a 2-3 log entities at the position of the tree
-                    let tree_position = state.entities[tree].position;
-                    let mut rng = rand::thread_rng();
-                    let num = rng.gen_range(2..=3);
-                    let x_vel_max = 30;
-                    let y_vel_max = 5;
-                    for _ in 0..num {
-                        let vel = Vec2::new(
-                            rng.gen_range(-x_vel_max..x_vel_max) as f32,
-                            rng.gen_range(-y_vel_max..y_vel_max) as f32,
-                        );
-                        let new_log = Entity {
-                            entity_type: EntityType::Log,
-                            position: tree_position,
-                            velocity: vel,
-                            size: Vec2::new(16.0, 16.0),
-                            sprite_animator: SpriteAnimator {
-                                sprite: Sprite::Log,
-                                current_frame: 0,
-                                current_time: 0.0,
-                                scale: 6.0,
-                            },
-                            follows: None,
-                            hp: 10.0,
-                            expire_in: Some(5.0),
-                            friction: Some(0.5),
-                            active: true,
-                        };
-
-                        state.add_entity(new_log);
-                    }
-                }
+    // when the axe lands, hurt tree and play chop sound
+    let impact = state
+        .animation_events
+        .iter()
+        .any(|event| event.entity == reticle_id && event.name == IMPACT_EVENT);
+    if impact && state.chop_cooldown <= 0.0 {
+        state.chop_cooldown = State::CHOP_COOLDOWN;
+        audio.play_sound_effect(SoundEffect::BaseballBatSwing);
+
+        for tree in trees {
+            let Some(tree) = state.get_mut(tree) else {
+                continue;
+            };
+            if tree.hp > 0.0 {
+                tree.hp -= 1.0;
             }
-        } else {
-            reticle.sprite_animator.set_sprite(Sprite::AxeIdle);
-        }
-    } else {
-        // default reticle sprite
-        reticle.sprite_animator.set_sprite(Sprite::Reticle);
-    }
+            tree.hit_flash = HIT_FLASH_SECONDS;
 
-    if state.chop_cooldown > 0.0 {
-        state.chop_cooldown -= rl.get_frame_time();
+            // spawn a 2-3 log entities at the position of the tree
+            let tree_position = tree.position;
+            let mut rng = rand::thread_rng();
+            let num = rng.gen_range(2..=3);
+            let x_vel_max = 30;
+            let y_vel_max = 5;
+            for _ in 0..num {
+                let vel = Vec2::new(
+                    rng.gen_range(-x_vel_max..x_vel_max) as f32,
+                    rng.gen_range(-y_vel_max..y_vel_max) as f32,
+                );
+                let new_log = Entity {
+                    velocity: vel,
+                    expire_in: Some(5.0),
+                    friction: Some(0.5),
+                    ..Entity::new(
+                        EntityType::Log,
+                        tree_position,
+                        game_sprites.log,
+                        &graphics.sprites,
+                    )
+                };
+
+                state.add_entity(new_log);
+            }
+        }
     }
 }
 
-pub fn become_chopped_if_dead_tree(state: &mut State) {
-    for entity in state.entities.iter_mut() {
+pub fn become_chopped_if_dead_tree(state: &mut State, game_sprites: &GameSprites) {
+    for (_, entity) in state.iter_mut() {
         if entity.entity_type != EntityType::Tree {
             continue;
         }
         if entity.hp <= 0.0 {
-            entity.sprite_animator.set_sprite(Sprite::TreeStump);
+            entity.sprite_animator.set_sprite(game_sprites.tree_stump);
         }
     }
 }
 
 pub fn apply_friction(state: &mut State, dt: f32) {
-    for entity in state.entities.iter_mut() {
+    for (_, entity) in state.iter_mut() {
         if let Some(friction) = entity.friction {
             entity.velocity *= 1.0 - friction * dt;
 
@@ -235,7 +332,7 @@ pub fn apply_friction(state: &mut State, dt: f32) {
 }
 
 pub fn step_expiring_entities(state: &mut State, dt: f32) {
-    for entity in state.entities.iter_mut() {
+    for (_, entity) in state.iter_mut() {
         // guard clause: skips entities that don't have an expire_in value
         if entity.expire_in.is_none() {
             continue;
@@ -251,47 +348,83 @@ pub fn step_expiring_entities(state: &mut State, dt: f32) {
     }
 }
 
+pub fn fade_expiring_entities(state: &mut State) {
+    for (_, entity) in state.iter_mut() {
+        if let Some(expire_in) = entity.expire_in {
+            entity.render.alpha = (expire_in / EXPIRE_FADE_SECONDS).clamp(0.0, 1.0);
+        }
+    }
+}
+
+/// Things that were just hit flash red and lean over, settling back as the flash runs out.
+pub fn step_hit_flashes(state: &mut State, dt: f32) {
+    const HIT_TILT_DEGREES: f32 = 8.0;
+    for (_, entity) in state.iter_mut() {
+        if entity.hit_flash <= 0.0 {
+            continue;
+        }
+        entity.hit_flash = (entity.hit_flash - dt).max(0.0);
+        let strength = entity.hit_flash / HIT_FLASH_SECONDS;
+        let other_channels = (255.0 * (1.0 - 0.6 * strength)) as u8;
+        entity.render.tint = Color::new(255, other_channels, other_channels, 255);
+        entity.render.rotation = HIT_TILT_DEGREES * strength;
+    }
+}
+
 pub fn prune_inactive_entities(state: &mut State) {
-    state.entities.retain(|entity| entity.active);
+    let inactive: Vec<EntityId> = state
+        .iter()
+        .filter(|(_, entity)| !entity.active)
+        .map(|(id, _)| id)
+        .collect();
+    for id in inactive {
+        state.remove(id);
+    }
 }
 
 pub fn do_following(state: &mut State) {
     let follow_dist = 10.0;
     let vel = 1000.0;
-    for i in 1..state.entities.len() {
-        if let Some(follows) = state.entities[i].follows {
-            if follows >= state.entities.len() {
-                state.entities[i].follows = None;
-                continue;
+    let followers: Vec<(EntityId, EntityId)> = state
+        .iter()
+        .filter_map(|(id, entity)| entity.follows.map(|follows| (id, follows)))
+        .collect();
+    for (id, follows) in followers {
+        let Some(target) = state.get(follows).map(|target| target.position) else {
+            // whatever it followed is gone
+            if let Some(entity) = state.get_mut(id) {
+                entity.follows = None;
             }
+            continue;
+        };
+        let Some(entity) = state.get_mut(id) else {
+            continue;
+        };
 
-            let target = state.entities[follows].position;
-
-            // seperate axis logic
-            // let x_diff = target.x - state.entities[i].position.x;
-            // let y_diff = target.y - state.entities[i].position.y;
-            // if x_diff > dist {
-            //     state.entities[i].velocity.x = vel;
-            // } else if x_diff < -dist {
-            //     state.entities[i].velocity.x = -vel;
-            // } else {
-            //     state.entities[i].velocity.x = 0.0;
-            // }
-            // if y_diff > dist {
-            //     state.entities[i].velocity.y = vel;
-            // } else if y_diff < -dist {
-            //     state.entities[i].velocity.y = -vel;
-            // } else {
-            //     state.entities[i].velocity.y = 0.0;
-            // }
-
-            // unified axis logic
-            let dir = target - state.entities[i].position;
-            if dir.length() > follow_dist {
-                state.entities[i].velocity = dir.normalize() * vel;
-            } else {
-                state.entities[i].velocity = Vec2::ZERO;
-            }
+        // seperate axis logic
+        // let x_diff = target.x - entity.position.x;
+        // let y_diff = target.y - entity.position.y;
+        // if x_diff > dist {
+        //     entity.velocity.x = vel;
+        // } else if x_diff < -dist {
+        //     entity.velocity.x = -vel;
+        // } else {
+        //     entity.velocity.x = 0.0;
+        // }
+        // if y_diff > dist {
+        //     entity.velocity.y = vel;
+        // } else if y_diff < -dist {
+        //     entity.velocity.y = -vel;
+        // } else {
+        //     entity.velocity.y = 0.0;
+        // }
+
+        // unified axis logic
+        let dir = target - entity.position;
+        if dir.length() > follow_dist {
+            entity.velocity = dir.normalize() * vel;
+        } else {
+            entity.velocity = Vec2::ZERO;
         }
     }
 }
@@ -301,18 +434,26 @@ pub fn step(
     state: &mut State,
     audio: &mut Audio,
     graphics: &mut Graphics,
+    game_sprites: &GameSprites,
+    animations: &[AnimationGraph],
     dt: f32,
 ) {
     apply_friction(state, dt);
     step_positions(state, dt);
     wrap_around_screen(state);
-    set_man_sprite_based_on_velocity(state);
+    set_movement_params(state);
+    face_velocity(state);
+    step_animation_states(state, animations, &graphics.sprites);
     step_sprites(state, &graphics.sprites, dt);
-    do_touch_apple(state, audio);
+    play_footsteps(state, audio);
+    fit_hitboxes_to_sprites(state, &graphics.sprites);
+    do_touch_apple(state, audio, graphics, game_sprites, animations);
     do_following(state);
-    determine_reticle_sprite(rl, state, audio);
-    become_chopped_if_dead_tree(state);
+    do_chopping(rl, state, audio, graphics, game_sprites);
+    become_chopped_if_dead_tree(state, game_sprites);
 
+    step_hit_flashes(state, dt);
     step_expiring_entities(state, dt);
+    fade_expiring_entities(state);
     prune_inactive_entities(state);
 }
@@ -0,0 +1,404 @@
+//! Where assets are read from: the loose asset folder while developing, or a pack in
+//! release. Loaders take paths relative to the asset root, like `sprites/apple.png`,
+//! so nothing depends on the working directory.
+//!
+//! Several roots can be stacked with a layers file, `layers.json` next to the asset
+//! folder, so a mod or seasonal reskin is just another folder or pack:
+//!
+//! ```json
+//! { "layers": ["assets", "mods/winter"] }
+//! ```
+//!
+//! Paths are relative to the layers file. Later layers override earlier ones file by
+//! file, and can add files of their own.
+//!
+//! With the `embed-assets` feature the asset folder is baked into the executable
+//! instead, see `build.rs`.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use serde_json::Value;
+
+use crate::{
+    error::AssetError,
+    pack::Pack,
+    settings::{ASSETS_FOLDER, LAYERS_FILE, PACK_FILE},
+};
+
+#[cfg(feature = "embed-assets")]
+mod embedded {
+    include!(concat!(env!("OUT_DIR"), "/embedded_assets.rs"));
+
+    pub fn get(path: &str) -> Option<&'static [u8]> {
+        let i = FILES.binary_search_by_key(&path, |(name, _)| name).ok()?;
+        Some(FILES[i].1)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Vfs {
+    /// files straight off disk, under this folder
+    Folder(PathBuf),
+    Pack(Pack),
+    /// the asset folder as it was when the executable was built
+    #[cfg(feature = "embed-assets")]
+    Embedded,
+    /// roots stacked bottom to top, never empty
+    Layers(Vec<Vfs>),
+}
+
+/// A path inside the asset root, `folder` may be empty for the root itself.
+pub fn join(folder: &str, file: &str) -> String {
+    if folder.is_empty() {
+        file.to_string()
+    } else {
+        format!("{}/{}", folder.trim_end_matches('/'), file)
+    }
+}
+
+/// The names of the paths directly inside `folder`.
+fn files_in<'a>(paths: impl Iterator<Item = &'a str>, folder: &str) -> Vec<String> {
+    let prefix = join(folder, "");
+    paths
+        .filter(|path| folder.is_empty() || path.starts_with(&prefix))
+        .map(|path| path.strip_prefix(&prefix).unwrap_or(path))
+        .filter(|name| !name.contains('/'))
+        .map(|name| name.to_string())
+        .collect()
+}
+
+impl Vfs {
+    /// A layers file if `path` is json, a pack if it is any other file, otherwise a
+    /// loose folder.
+    pub fn open(path: &Path) -> Result<Self, AssetError> {
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "json")
+        {
+            Self::open_layers(path)
+        } else if path.is_file() {
+            Ok(Vfs::Pack(Pack::open(path)?))
+        } else {
+            Ok(Vfs::Folder(path.to_path_buf()))
+        }
+    }
+
+    /// Stacks the folders and packs listed in a layers file.
+    pub fn open_layers(path: &Path) -> Result<Self, AssetError> {
+        let bytes = fs::read(path).map_err(|e| AssetError::io(path, e))?;
+        let json: Value = serde_json::from_slice(&bytes).map_err(|source| AssetError::Json {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let invalid = |field: String, cause: &str| AssetError::InvalidField {
+            path: path.to_path_buf(),
+            frame: None,
+            field,
+            cause: cause.to_string(),
+        };
+        let layers_data = json["layers"]
+            .as_array()
+            .ok_or_else(|| invalid("layers".to_string(), "expected an array"))?;
+        if layers_data.is_empty() {
+            return Err(invalid("layers".to_string(), "needs at least one layer"));
+        }
+
+        let base_folder = path.parent().unwrap_or(Path::new("."));
+        let mut layers = Vec::with_capacity(layers_data.len());
+        let mut missing_files = vec![];
+        for (i, layer_data) in layers_data.iter().enumerate() {
+            let layer_path = layer_data
+                .as_str()
+                .map(|layer| base_folder.join(layer))
+                .ok_or_else(|| invalid(format!("layers[{}]", i), "expected a path"))?;
+            if layer_path.is_file() {
+                layers.push(Vfs::Pack(Pack::open(&layer_path)?));
+            } else if layer_path.is_dir() {
+                layers.push(Vfs::Folder(layer_path));
+            } else {
+                missing_files.push(layer_path);
+            }
+        }
+        if !missing_files.is_empty() {
+            return Err(AssetError::MissingFiles(missing_files));
+        }
+        Ok(Vfs::Layers(layers))
+    }
+
+    /// Finds the game's assets wherever it was launched from. Embedded assets always win.
+    /// Debug builds use the crate's own folder so edits show up, otherwise the folder the
+    /// executable is in, then the working directory. In each a layers file comes first,
+    /// then a pack, then a loose asset folder.
+    pub fn locate() -> Result<Self, AssetError> {
+        #[cfg(feature = "embed-assets")]
+        return Ok(Vfs::Embedded);
+
+        #[allow(unreachable_code)]
+        let crate_folder = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let exe_folder = env::current_exe()
+            .ok()
+            .and_then(|exe| exe.parent().map(Path::to_path_buf));
+        let mut folders = vec![];
+        if cfg!(debug_assertions) {
+            folders.push(crate_folder.clone());
+        }
+        folders.extend(exe_folder);
+        folders.push(PathBuf::from("."));
+        folders.push(crate_folder);
+
+        let mut tried = vec![];
+        for folder in folders {
+            let layers_path = folder.join(LAYERS_FILE);
+            if layers_path.is_file() {
+                return Self::open_layers(&layers_path);
+            }
+            let pack_path = folder.join(PACK_FILE);
+            if pack_path.is_file() {
+                return Ok(Vfs::Pack(Pack::open(&pack_path)?));
+            }
+            let assets_path = folder.join(ASSETS_FOLDER);
+            if assets_path.is_dir() {
+                return Ok(Vfs::Folder(assets_path));
+            }
+            tried.push(assets_path);
+        }
+        Err(AssetError::MissingFiles(tried))
+    }
+
+    /// The layer a path is read from, the topmost one that has it.
+    fn layer_of(&self, path: &str) -> &Vfs {
+        self.layer_with(&[path])
+    }
+
+    /// The topmost layer that has any of `paths`, so files that belong together, like a
+    /// sprite's png and json, come from the same place. The top layer if none do.
+    pub fn layer_with(&self, paths: &[&str]) -> &Vfs {
+        match self {
+            Vfs::Layers(layers) => {
+                let found = layers
+                    .iter()
+                    .rev()
+                    .find(|layer| paths.iter().any(|path| layer.exists(path)));
+                found.unwrap_or(&layers[layers.len() - 1]).layer_with(paths)
+            }
+            _ => self,
+        }
+    }
+
+    /// Where a path points, for error messages. Inside a pack this isn't a real file.
+    pub fn full_path(&self, path: &str) -> PathBuf {
+        match self {
+            Vfs::Folder(root) => root.join(path),
+            Vfs::Pack(pack) => pack.path().join(path),
+            #[cfg(feature = "embed-assets")]
+            Vfs::Embedded => Path::new("<embedded>").join(path),
+            Vfs::Layers(_) => self.layer_of(path).full_path(path),
+        }
+    }
+
+    /// The path on disk, only loose folders have one.
+    pub fn disk_path(&self, path: &str) -> Option<PathBuf> {
+        match self {
+            Vfs::Folder(root) => Some(root.join(path)),
+            Vfs::Layers(_) => self.layer_of(path).disk_path(path),
+            _ => None,
+        }
+    }
+
+    /// `path` in every loose folder layer, whether it is there or not, for watching.
+    pub fn disk_paths(&self, path: &str) -> Vec<PathBuf> {
+        match self {
+            Vfs::Layers(layers) => layers
+                .iter()
+                .flat_map(|layer| layer.disk_paths(path))
+                .collect(),
+            _ => self.disk_path(path).into_iter().collect(),
+        }
+    }
+
+    pub fn exists(&self, path: &str) -> bool {
+        match self {
+            Vfs::Folder(root) => root.join(path).is_file(),
+            Vfs::Pack(pack) => pack.contains(path),
+            #[cfg(feature = "embed-assets")]
+            Vfs::Embedded => embedded::get(path).is_some(),
+            Vfs::Layers(layers) => layers.iter().any(|layer| layer.exists(path)),
+        }
+    }
+
+    pub fn read(&self, path: &str) -> Result<Vec<u8>, AssetError> {
+        match self {
+            Vfs::Folder(root) => {
+                let full_path = root.join(path);
+                fs::read(&full_path).map_err(|e| AssetError::io(full_path, e))
+            }
+            Vfs::Pack(pack) => pack.read(path),
+            #[cfg(feature = "embed-assets")]
+            Vfs::Embedded => embedded::get(path)
+                .map(|bytes| bytes.to_vec())
+                .ok_or_else(|| AssetError::MissingFiles(vec![self.full_path(path)])),
+            Vfs::Layers(_) => self.layer_of(path).read(path),
+        }
+    }
+
+    pub fn read_json(&self, path: &str) -> Result<Value, AssetError> {
+        let bytes = self.read(path)?;
+        serde_json::from_slice(&bytes).map_err(|source| AssetError::Json {
+            path: self.full_path(path),
+            source,
+        })
+    }
+
+    /// Names of the files directly inside `folder`, sorted.
+    pub fn list(&self, folder: &str) -> Result<Vec<String>, AssetError> {
+        let mut names = vec![];
+        match self {
+            Vfs::Folder(root) => {
+                let folder_path = root.join(folder);
+                let entries =
+                    fs::read_dir(&folder_path).map_err(|e| AssetError::io(&folder_path, e))?;
+                for entry in entries {
+                    let path = entry.map_err(|e| AssetError::io(&folder_path, e))?.path();
+                    let name = path.file_name().and_then(|name| name.to_str());
+                    if let (true, Some(name)) = (path.is_file(), name) {
+                        names.push(name.to_string());
+                    }
+                }
+            }
+            Vfs::Pack(pack) => names = files_in(pack.names(), folder),
+            #[cfg(feature = "embed-assets")]
+            Vfs::Embedded => {
+                names = files_in(embedded::FILES.iter().map(|(name, _)| *name), folder)
+            }
+            Vfs::Layers(layers) => {
+                // a layer only needs the folders it adds to
+                let mut first_error = None;
+                let mut found = false;
+                for layer in layers {
+                    match layer.list(folder) {
+                        Ok(layer_names) => {
+                            found = true;
+                            names.extend(layer_names);
+                        }
+                        Err(e) => {
+                            first_error.get_or_insert(e);
+                        }
+                    }
+                }
+                if let (false, Some(e)) = (found, first_error) {
+                    return Err(e);
+                }
+            }
+        }
+        names.sort();
+        names.dedup();
+        Ok(names)
+    }
+
+    /// When a file last changed, packs don't keep track.
+    pub fn modified(&self, path: &str) -> Option<SystemTime> {
+        let disk_path = self.disk_path(path)?;
+        fs::metadata(disk_path)
+            .and_then(|meta| meta.modified())
+            .ok()
+    }
+}
+
+/// A fresh empty folder under the system temp folder.
+#[cfg(test)]
+pub(crate) fn temp_folder(name: &str) -> PathBuf {
+    let folder = env::temp_dir().join(format!("spriteloading-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&folder);
+    fs::create_dir_all(&folder).unwrap();
+    folder
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sprite::load_sprite;
+
+    /// Writes `files` under `folder`, making folders as needed.
+    fn write_files(folder: &Path, files: &[(&str, &[u8])]) {
+        for (name, bytes) in files {
+            let path = folder.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, bytes).unwrap();
+        }
+    }
+
+    /// A layers file stacking `layers` inside `folder`.
+    fn open_stack(folder: &Path, layers: &[&str]) -> Result<Vfs, AssetError> {
+        let layers_path = folder.join(LAYERS_FILE);
+        let json = serde_json::json!({ "layers": layers });
+        fs::write(&layers_path, json.to_string()).unwrap();
+        Vfs::open(&layers_path)
+    }
+
+    #[test]
+    fn the_topmost_layer_wins() {
+        let folder = temp_folder("vfs-override");
+        write_files(
+            &folder.join("base"),
+            &[("notes/a.txt", b"base"), ("notes/b.txt", b"base")],
+        );
+        write_files(
+            &folder.join("mod"),
+            &[("notes/a.txt", b"mod"), ("notes/c.txt", b"mod")],
+        );
+        let vfs = open_stack(&folder, &["base", "mod"]).unwrap();
+
+        assert_eq!(vfs.read("notes/a.txt").unwrap(), b"mod");
+        assert_eq!(vfs.read("notes/b.txt").unwrap(), b"base");
+        assert_eq!(vfs.full_path("notes/a.txt"), folder.join("mod/notes/a.txt"));
+        assert_eq!(vfs.list("notes").unwrap(), ["a.txt", "b.txt", "c.txt"]);
+
+        // the other way up the base wins
+        let vfs = open_stack(&folder, &["mod", "base"]).unwrap();
+        assert_eq!(vfs.read("notes/a.txt").unwrap(), b"base");
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn sprite_art_comes_from_one_layer() {
+        let folder = temp_folder("vfs-sprite");
+        let png = fs::read("assets/sprites/apple.png").unwrap();
+        let json = fs::read("assets/sprites/apple.json").unwrap();
+        write_files(
+            &folder.join("base"),
+            &[("sprites/apple.png", &png), ("sprites/apple.json", &json)],
+        );
+        write_files(&folder.join("mod"), &[("sprites/apple.png", &png)]);
+        let vfs = open_stack(&folder, &["base", "mod"]).unwrap();
+
+        let art = vfs.layer_with(&["sprites/apple.png", "sprites/apple.json"]);
+        assert_eq!(art.full_path(""), folder.join("mod"));
+        // the mod's png doesn't get paired with the base json
+        let Err(AssetError::MissingFiles(files)) = load_sprite(&vfs, "sprites", "apple") else {
+            panic!("expected the mod's json to be missing");
+        };
+        assert!(files.contains(&folder.join("mod/sprites/apple.json")));
+
+        write_files(&folder.join("mod"), &[("sprites/apple.json", &json)]);
+        assert!(load_sprite(&vfs, "sprites", "apple").is_ok());
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn missing_layers_are_an_error() {
+        let folder = temp_folder("vfs-missing");
+        fs::create_dir_all(folder.join("base")).unwrap();
+        match open_stack(&folder, &["base", "mods/winter"]) {
+            Err(AssetError::MissingFiles(files)) => {
+                assert_eq!(files, [folder.join("mods/winter")])
+            }
+            result => panic!("expected a missing layer, got {:?}", result),
+        }
+        assert!(open_stack(&folder, &[]).is_err());
+        fs::remove_dir_all(&folder).unwrap();
+    }
+}
@@ -0,0 +1,104 @@
+//! Notices edited sprite files so they can be reloaded while the game runs.
+//! Polls modification times, which is plenty for a few folders of art.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::sprite::sprite_name_of;
+
+pub struct AssetWatcher {
+    /// one per asset layer, a sprite is named the same in each
+    folders: Vec<PathBuf>,
+    /// last seen modification time of every sprite file
+    modified: HashMap<PathBuf, SystemTime>,
+    /// sprite name to when one of its files last changed
+    pending: HashMap<String, Instant>,
+    /// a sprite is only reported once its files have been still this long,
+    /// so an export that is still being written isn't picked up half done
+    debounce: Duration,
+    poll_interval: Duration,
+    last_poll: Instant,
+}
+
+impl AssetWatcher {
+    pub fn new(folders: Vec<PathBuf>, poll_interval: Duration, debounce: Duration) -> Self {
+        let modified = scan(&folders);
+        Self {
+            folders,
+            modified,
+            pending: HashMap::new(),
+            debounce,
+            poll_interval,
+            last_poll: Instant::now(),
+        }
+    }
+
+    /// Names of sprites whose files changed and have since settled, sorted.
+    /// Cheap to call every frame, the folder is only scanned every `poll_interval`.
+    pub fn poll(&mut self) -> Vec<String> {
+        let now = Instant::now();
+        if now.duration_since(self.last_poll) >= self.poll_interval {
+            self.last_poll = now;
+            self.scan_for_changes(now);
+        }
+
+        let mut settled: Vec<String> = self
+            .pending
+            .iter()
+            .filter(|(_, changed)| now.duration_since(**changed) >= self.debounce)
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in settled.iter() {
+            self.pending.remove(name);
+        }
+        settled.sort();
+        settled
+    }
+
+    fn scan_for_changes(&mut self, now: Instant) {
+        let modified = scan(&self.folders);
+        // new and edited files
+        for (path, time) in modified.iter() {
+            if self.modified.get(path) != Some(time) {
+                if let Some(name) = sprite_name_of(path) {
+                    self.pending.insert(name.to_string(), now);
+                }
+            }
+        }
+        // deleted files
+        for path in self.modified.keys() {
+            if !modified.contains_key(path) {
+                if let Some(name) = sprite_name_of(path) {
+                    self.pending.insert(name.to_string(), now);
+                }
+            }
+        }
+        self.modified = modified;
+    }
+}
+
+/// Modification times of the png, json and aseprite files in `folders`, sidecars included.
+fn scan(folders: &[PathBuf]) -> HashMap<PathBuf, SystemTime> {
+    let mut modified = HashMap::new();
+    let paths = folders
+        .iter()
+        .filter_map(|folder| fs::read_dir(folder).ok())
+        .flat_map(|entries| entries.flatten().map(|entry| entry.path()));
+    for path in paths {
+        let is_sprite_file = matches!(
+            path.extension().and_then(|extension| extension.to_str()),
+            Some("png" | "json" | "aseprite")
+        );
+        if !is_sprite_file {
+            continue;
+        }
+        if let Ok(time) = fs::metadata(&path).and_then(|meta| meta.modified()) {
+            modified.insert(path, time);
+        }
+    }
+    modified
+}